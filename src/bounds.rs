@@ -1,8 +1,36 @@
-use std::iter;
+use std::fmt;
 
-use resvg::tiny_skia::{self, PixmapMut};
+use resvg::tiny_skia::{self, PixmapMut, PremultipliedColorU8};
+use serde::Deserialize;
+use thiserror::Error;
 
-#[derive(Debug)]
+use crate::{notation::to_hex, paint, parser::Color};
+
+/// How scaled bounds are rounded to whole pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    // prefer ceil over round by default
+    // rationale:
+    //   if a range of 5 is fixed size, then a range of 6 might be fixed size, while 4 will likely be too small
+    //   prefer larger values rather than rounding to nearest value
+    #[default]
+    Ceil,
+    Round,
+    Floor,
+}
+
+impl Rounding {
+    fn apply(self, value: f32) -> f32 {
+        match self {
+            Rounding::Ceil => value.ceil(),
+            Rounding::Round => value.round(),
+            Rounding::Floor => value.floor(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct Bounds {
     pub l: u32,
     pub r: u32,
@@ -15,92 +43,95 @@ impl Bounds {
         self.l == 0 && self.r == 0 && self.t == 0 && self.b == 0
     }
 
-    fn scale_value(value: u32, amount: f32) -> u32 {
+    fn scale_value(value: u32, amount: f32, rounding: Rounding) -> u32 {
         if value == 0 {
             return 0;
         }
 
-        // prefer ceil over round
-        // rationale:
-        //   if a range of 5 is fixed size, then a range of 6 might be fixed size, while 4 will likely be too small
-        //   prefer larger values rather than rounding to nearest value
-        (value as f32 * amount).ceil().max(1.0) as u32
+        // a bound never disappears when downscaling
+        rounding.apply(value as f32 * amount).max(1.0) as u32
     }
 
-    pub fn scale(&self, amount: f32) -> Self {
+    /// Scale the left and right bounds by `scale_x`, and the top and bottom bounds by
+    /// `scale_y`
+    pub fn scale(&self, scale_x: f32, scale_y: f32, rounding: Rounding) -> Self {
         Self {
-            l: Self::scale_value(self.l, amount),
-            r: Self::scale_value(self.r, amount),
-            t: Self::scale_value(self.t, amount),
-            b: Self::scale_value(self.b, amount),
+            l: Self::scale_value(self.l, scale_x, rounding),
+            r: Self::scale_value(self.r, scale_x, rounding),
+            t: Self::scale_value(self.t, scale_y, rounding),
+            b: Self::scale_value(self.b, scale_y, rounding),
         }
     }
 
-    pub fn paint(&self, pixmap: &mut tiny_skia::PixmapMut, paint: &tiny_skia::Paint) {
-        if self.is_empty() {
-            return;
-        }
+    /// Whether the bounds fit inside an image of the given inner size, i.e. the size
+    /// without the 1px border, without opposite bounds overlapping
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.l + self.r <= width && self.t + self.b <= height
+    }
 
+    /// Paint the bounds on the given sides of the 1px border. The corner pixel is painted
+    /// even where a bound is zero, so that the side stays marked.
+    pub fn paint(
+        &self,
+        pixmap: &mut tiny_skia::PixmapMut,
+        paint: &tiny_skia::Paint,
+        sides: &[Side],
+    ) {
         let img_width = pixmap.width() as f32;
         let img_height = pixmap.height() as f32;
 
-        pixmap.fill_rect(
-            tiny_skia::Rect::from_xywh(0.0, 0.0, (self.l + 1) as f32, 1.0).unwrap(),
-            &paint,
-            tiny_skia::Transform::identity(),
-            None,
-        );
-        pixmap.fill_rect(
-            tiny_skia::Rect::from_xywh(0.0, 0.0, 1.0, (self.t + 1) as f32).unwrap(),
-            &paint,
-            tiny_skia::Transform::identity(),
-            None,
-        );
-        pixmap.fill_rect(
-            tiny_skia::Rect::from_xywh(
-                img_width - (self.r + 1) as f32,
-                img_height - 1.0,
-                (self.r + 1) as f32,
-                1.0,
-            )
-            .unwrap(),
-            &paint,
-            tiny_skia::Transform::identity(),
-            None,
-        );
-        pixmap.fill_rect(
-            tiny_skia::Rect::from_xywh(
-                img_width - 1.0,
-                img_height - (self.b + 1) as f32,
-                1.0,
-                (self.b + 1) as f32,
-            )
-            .unwrap(),
-            &paint,
-            tiny_skia::Transform::identity(),
-            None,
-        );
+        for side in sides {
+            let rect = match side {
+                Side::Top => tiny_skia::Rect::from_xywh(0.0, 0.0, (self.l + 1) as f32, 1.0),
+                Side::Left => tiny_skia::Rect::from_xywh(0.0, 0.0, 1.0, (self.t + 1) as f32),
+                Side::Bottom => tiny_skia::Rect::from_xywh(
+                    img_width - (self.r + 1) as f32,
+                    img_height - 1.0,
+                    (self.r + 1) as f32,
+                    1.0,
+                ),
+                Side::Right => tiny_skia::Rect::from_xywh(
+                    img_width - 1.0,
+                    img_height - (self.b + 1) as f32,
+                    1.0,
+                    (self.b + 1) as f32,
+                ),
+            };
+            pixmap.fill_rect(rect.unwrap(), paint, tiny_skia::Transform::identity(), None);
+        }
     }
 }
 
-impl Default for Bounds {
-    fn default() -> Self {
-        Self {
-            l: Default::default(),
-            r: Default::default(),
-            t: Default::default(),
-            b: Default::default(),
+impl Bounds {
+    /// Parse bounds written as `l,t,r,b`, e.g. `4,0,4,0`
+    fn parse(text: &str) -> Option<Self> {
+        let values: Vec<u32> = text
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match values[..] {
+            [l, t, r, b] => Some(Self { l, t, r, b }),
+            _ => None,
         }
     }
 }
 
+/// The REAPER borders of an image
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ReaperBounds {
+    pub yellow: Bounds,
+    pub pink: Bounds,
+    /// The edges that have markers. Fully transparent edges are missing, and their bounds
+    /// are zero.
+    pub sides: Vec<Side>,
+}
+
 /// Erase a 1px border around the image
 pub fn erase_bounds(pixmap: &mut PixmapMut) {
-    let eraser_paint = {
-        let mut paint = tiny_skia::Paint::default();
-        paint.anti_alias = false;
-        paint.blend_mode = tiny_skia::BlendMode::Clear;
-        paint
+    let eraser_paint = tiny_skia::Paint {
+        anti_alias: false,
+        blend_mode: tiny_skia::BlendMode::Clear,
+        ..Default::default()
     };
 
     let width = pixmap.width() as f32;
@@ -139,57 +170,141 @@ enum BoundPixel {
     Transparent,
 }
 
+/// An edge of the image. Each edge is read from one corner and sets one side of the
+/// bounds: the top edge sets the left bounds, the left edge sets the top bounds, etc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Top,
+    Left,
+    Bottom,
+    Right,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Side::Top => "top",
+            Side::Left => "left",
+            Side::Bottom => "bottom",
+            Side::Right => "right",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The rule of REAPER's border format that a pixel broke
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundsRule {
+    #[error("border pixels must be pink, yellow or transparent")]
+    InvalidColor,
+    #[error("the edge must start with a pink or yellow pixel in the corner")]
+    MissingCorner,
+    #[error("yellow can't come after pink")]
+    YellowAfterPink,
+    #[error("pink or yellow can't come after a transparent gap")]
+    ColorAfterGap,
+}
+
+/// Why the borders of an image couldn't be detected
+#[derive(Error, Debug, PartialEq)]
+pub enum BoundsError {
+    #[error("image of size {0}x{1} is too small for borders, it must be at least 3x3")]
+    TooSmall(u32, u32),
+    #[error("{side} edge, pixel ({x}, {y}) is {}: {rule}", to_hex(.color))]
+    InvalidPixel {
+        side: Side,
+        x: u32,
+        y: u32,
+        color: Color,
+        rule: BoundsRule,
+    },
+    #[error("invalid {0} value {1:?}, expected 4 whole numbers l,t,r,b such as \"4,0,4,0\"")]
+    InvalidMetadata(&'static str, String),
+}
+
+fn pixel_color(pixel: PremultipliedColorU8) -> Color {
+    let pixel = pixel.demultiply();
+    match pixel.alpha() {
+        255 => Color::RGB(pixel.red(), pixel.green(), pixel.blue()),
+        a => Color::RGBA(pixel.red(), pixel.green(), pixel.blue(), a),
+    }
+}
+
+/// The pixels of an edge, in the order they are read
+fn edge_pixels(img: &tiny_skia::Pixmap, side: Side) -> Vec<(u32, u32)> {
+    let (width, height) = (img.width(), img.height());
+    match side {
+        // from top left->right
+        Side::Top => (0..width).map(|x| (x, 0)).collect(),
+        // from left top->bottom
+        Side::Left => (0..height).map(|y| (0, y)).collect(),
+        // from bottom right->left
+        Side::Bottom => (0..width).rev().map(|x| (x, height - 1)).collect(),
+        // from right bottom->top
+        Side::Right => (0..height).rev().map(|y| (width - 1, y)).collect(),
+    }
+}
+
 /// Return (yellow, pink) bound widths (subtracted by 2 to ignore the 1px border)
-/// `Some` means it has a 1px border. `None` means it has no border.
+/// `Ok(None)` means the edge is fully transparent, i.e. it has no markers. `Err` is the pixel
+/// where the border is invalid.
 fn parse_bound_side(
-    img: &resvg::tiny_skia::Pixmap,
-    x_iter: impl Iterator<Item = u32>,
-    y_iter: impl Iterator<Item = u32>,
-) -> Option<(u32, u32)> {
-    let x_iter: Vec<_> = x_iter.collect();
-    let y_iter: Vec<_> = y_iter.collect();
+    img: &tiny_skia::Pixmap,
+    side: Side,
+) -> Result<Option<(u32, u32)>, BoundsError> {
+    let coords = edge_pixels(img, side);
 
     let mut result = Vec::new();
 
-    for x in x_iter.iter() {
-        for y in y_iter.iter() {
-            let pixel = img
-                .pixel(*x, *y)
-                .expect(format!("pixel out of bounds ({x}, {y})").as_str());
-            let is_empty = pixel.alpha() == 0;
-            if is_empty {
-                result.push(BoundPixel::Transparent);
-                continue;
-            }
-
-            let is_yellow = pixel.alpha() == 255
-                && pixel.red() == 255
-                && pixel.green() == 255
-                && pixel.blue() == 0;
-            if is_yellow {
-                result.push(BoundPixel::Yellow);
-                continue;
-            }
+    for &(x, y) in coords.iter() {
+        let pixel = img
+            .pixel(x, y)
+            .unwrap_or_else(|| panic!("pixel out of bounds ({x}, {y})"));
+        let is_empty = pixel.alpha() == 0;
+        if is_empty {
+            result.push(BoundPixel::Transparent);
+            continue;
+        }
 
-            let is_pink = pixel.alpha() == 255
-                && pixel.red() == 255
-                && pixel.green() == 0
-                && pixel.blue() == 255;
-            if is_pink {
-                result.push(BoundPixel::Pink);
-                continue;
-            }
+        let is_yellow =
+            pixel.alpha() == 255 && pixel.red() == 255 && pixel.green() == 255 && pixel.blue() == 0;
+        if is_yellow {
+            result.push(BoundPixel::Yellow);
+            continue;
+        }
 
-            // encountered invalid pixel, therefore this is not a valid REAPER bound border
-            return None;
+        let is_pink =
+            pixel.alpha() == 255 && pixel.red() == 255 && pixel.green() == 0 && pixel.blue() == 255;
+        if is_pink {
+            result.push(BoundPixel::Pink);
+            continue;
         }
+
+        // encountered invalid pixel, therefore this is not a valid REAPER bound border
+        return Err(BoundsError::InvalidPixel {
+            side,
+            x,
+            y,
+            color: pixel_color(pixel),
+            rule: BoundsRule::InvalidColor,
+        });
     }
 
-    // image must be minimum of 3 pixels in width / height
-    if result.len() < 3 {
-        return None;
+    if result.iter().all(|pixel| *pixel == BoundPixel::Transparent) {
+        return Ok(None);
     }
 
+    let invalid = |i: usize, rule: BoundsRule| {
+        let (x, y) = coords[i];
+        BoundsError::InvalidPixel {
+            side,
+            x,
+            y,
+            color: pixel_color(img.pixel(x, y).unwrap()),
+            rule,
+        }
+    };
+
     // find the semantic width of the yellow/pink lines
     // e.g. if a pink line is 3px long, it represents a 2px region
     let mut yellow_width: u32 = 0;
@@ -206,7 +321,7 @@ fn parse_bound_side(
                     prev_pixel = Some(BoundPixel::Pink);
                     pink_width = i as u32;
                 }
-                BoundPixel::Transparent => return None,
+                BoundPixel::Transparent => return Err(invalid(i, BoundsRule::MissingCorner)),
             },
             Some(BoundPixel::Yellow) => match pixel {
                 BoundPixel::Yellow => {
@@ -224,48 +339,289 @@ fn parse_bound_side(
                 BoundPixel::Pink => pink_width = i as u32,
                 BoundPixel::Transparent => prev_pixel = Some(BoundPixel::Transparent),
                 // invalid sequence, pink -> yellow
-                BoundPixel::Yellow => return None,
+                BoundPixel::Yellow => return Err(invalid(i, BoundsRule::YellowAfterPink)),
             },
             Some(BoundPixel::Transparent) => match pixel {
                 BoundPixel::Transparent => continue,
                 // invalid sequences, transparent -> yellow/pink
-                BoundPixel::Yellow => return None,
-                BoundPixel::Pink => return None,
+                BoundPixel::Yellow | BoundPixel::Pink => {
+                    return Err(invalid(i, BoundsRule::ColorAfterGap))
+                }
             },
         }
     }
 
     let max_width = (result.len() - 2) as u32;
 
-    Some((yellow_width.min(max_width), pink_width.min(max_width)))
+    Ok(Some((
+        yellow_width.min(max_width),
+        pink_width.min(max_width),
+    )))
 }
 
-pub fn detect_reaper_bounds(img: &resvg::tiny_skia::Pixmap) -> Option<(Bounds, Bounds)> {
-    if img.width() == 0 || img.height() == 0 {
-        return None;
+/// Parse the bounds from the 1px border of an image, or explain why the border isn't valid.
+/// Edges without markers are allowed, as long as the marked edges set some bounds: a lone
+/// pink or yellow corner pixel could just as well be artwork.
+pub fn parse_reaper_bounds(img: &tiny_skia::Pixmap) -> Result<ReaperBounds, BoundsError> {
+    // image must be minimum of 3 pixels in width / height
+    if img.width() < 3 || img.height() < 3 {
+        return Err(BoundsError::TooSmall(img.width(), img.height()));
     }
 
-    // from top left->right
-    let left = parse_bound_side(&img, 0..img.width(), iter::once(0))?;
-    // from left top->bottom
-    let top = parse_bound_side(&img, iter::once(0), 0..img.height())?;
-    // from bottom right->left
-    let right = parse_bound_side(&img, (0..img.width()).rev(), iter::once(img.height() - 1))?;
-    // from right bottom->top
-    let bottom = parse_bound_side(&img, iter::once(img.width() - 1), (0..img.height()).rev())?;
-
-    let yellow_bounds = Bounds {
-        t: top.0,
-        l: left.0,
-        b: bottom.0,
-        r: right.0,
-    };
-    let pink_bounds = Bounds {
-        t: top.1,
-        l: left.1,
-        b: bottom.1,
-        r: right.1,
+    let mut bounds = ReaperBounds::default();
+    for side in [Side::Top, Side::Left, Side::Bottom, Side::Right] {
+        let Some((yellow, pink)) = parse_bound_side(img, side)? else {
+            continue;
+        };
+        let (yellow_side, pink_side) = match side {
+            Side::Top => (&mut bounds.yellow.l, &mut bounds.pink.l),
+            Side::Left => (&mut bounds.yellow.t, &mut bounds.pink.t),
+            Side::Bottom => (&mut bounds.yellow.r, &mut bounds.pink.r),
+            Side::Right => (&mut bounds.yellow.b, &mut bounds.pink.b),
+        };
+        (*yellow_side, *pink_side) = (yellow, pink);
+        bounds.sides.push(side);
+    }
+
+    let is_partial = bounds.sides.len() < 4;
+    if is_partial && bounds.yellow.is_empty() && bounds.pink.is_empty() {
+        // report the corner of the first edge without markers
+        let side = [Side::Top, Side::Left, Side::Bottom, Side::Right]
+            .into_iter()
+            .find(|side| !bounds.sides.contains(side))
+            .unwrap();
+        let (x, y) = edge_pixels(img, side)[0];
+        return Err(BoundsError::InvalidPixel {
+            side,
+            x,
+            y,
+            color: pixel_color(img.pixel(x, y).unwrap()),
+            rule: BoundsRule::MissingCorner,
+        });
+    }
+
+    Ok(bounds)
+}
+
+impl ReaperBounds {
+    /// Bounds with markers on every edge, for inserting a border around an image
+    pub fn new(yellow: Bounds, pink: Bounds) -> Self {
+        Self {
+            yellow,
+            pink,
+            sides: vec![Side::Top, Side::Left, Side::Bottom, Side::Right],
+        }
+    }
+}
+
+/// Read the bounds of a border to insert from the root element of an SVG, e.g.
+/// `<svg data-reaper-pink="4,0,4,0">`. The bounds are in the SVG's pixels, not counting the
+/// 1px border. `None` if the SVG has neither attribute.
+pub fn metadata_bounds(xml: &str) -> Result<Option<ReaperBounds>, BoundsError> {
+    let attributes = paint::root_attributes(xml);
+    let read = |name: &'static str| match attributes.iter().find(|(other, _)| *other == name) {
+        Some(&(_, value)) => Bounds::parse(value)
+            .map(Some)
+            .ok_or_else(|| BoundsError::InvalidMetadata(name, value.to_owned())),
+        None => Ok(None),
     };
+    let yellow = read("data-reaper-yellow")?;
+    let pink = read("data-reaper-pink")?;
+    if yellow.is_none() && pink.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(ReaperBounds::new(
+        yellow.unwrap_or_default(),
+        pink.unwrap_or_default(),
+    )))
+}
+
+/// Whether the edges of an image have any pink or yellow pixels, i.e. it looks like it was
+/// meant to have borders
+pub fn has_border_pixels(img: &tiny_skia::Pixmap) -> bool {
+    [Side::Top, Side::Left, Side::Bottom, Side::Right]
+        .into_iter()
+        .flat_map(|side| edge_pixels(img, side))
+        .any(|(x, y)| {
+            img.pixel(x, y)
+                .is_some_and(|pixel| pixel_color(pixel).is_reaper_reserved())
+        })
+}
 
-    Some((yellow_bounds, pink_bounds))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixmap(width: u32, height: u32, pixels: &[(u32, u32, [u8; 4])]) -> tiny_skia::Pixmap {
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).unwrap();
+        for &(x, y, [r, g, b, a]) in pixels {
+            let color = tiny_skia::ColorU8::from_rgba(r, g, b, a).premultiply();
+            let index = (y * width + x) as usize;
+            pixmap.pixels_mut()[index] = color;
+        }
+        pixmap
+    }
+
+    const PINK: [u8; 4] = [255, 0, 255, 255];
+    const YELLOW: [u8; 4] = [255, 255, 0, 255];
+
+    #[test]
+    fn test_bounds_errors() {
+        let corners = [(0, 0, PINK), (4, 3, PINK)];
+        let bounds = parse_reaper_bounds(&pixmap(5, 4, &corners)).unwrap();
+        assert!(bounds.pink.is_empty());
+
+        assert_eq!(
+            parse_reaper_bounds(&pixmap(2, 4, &[])).unwrap_err(),
+            BoundsError::TooSmall(2, 4)
+        );
+        assert_eq!(
+            parse_reaper_bounds(&pixmap(5, 4, &[(2, 2, PINK)])).unwrap_err(),
+            BoundsError::InvalidPixel {
+                side: Side::Top,
+                x: 0,
+                y: 0,
+                color: Color::RGBA(0, 0, 0, 0),
+                rule: BoundsRule::MissingCorner,
+            }
+        );
+        assert_eq!(
+            parse_reaper_bounds(&pixmap(5, 4, &[(0, 0, PINK), (0, 1, YELLOW), (4, 3, PINK)]))
+                .unwrap_err(),
+            BoundsError::InvalidPixel {
+                side: Side::Left,
+                x: 0,
+                y: 1,
+                color: Color::RGB(255, 255, 0),
+                rule: BoundsRule::YellowAfterPink,
+            }
+        );
+        assert_eq!(
+            parse_reaper_bounds(&pixmap(5, 4, &[(0, 0, PINK), (3, 0, PINK), (4, 3, PINK)]))
+                .unwrap_err(),
+            BoundsError::InvalidPixel {
+                side: Side::Top,
+                x: 3,
+                y: 0,
+                color: Color::RGB(255, 0, 255),
+                rule: BoundsRule::ColorAfterGap,
+            }
+        );
+
+        let error = parse_reaper_bounds(&pixmap(5, 4, &[(0, 0, PINK), (2, 3, [1, 2, 3, 255])]));
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "bottom edge, pixel (2, 3) is #010203: border pixels must be pink, yellow or transparent"
+        );
+        assert!(has_border_pixels(&pixmap(5, 4, &[(2, 3, YELLOW)])));
+        assert!(!has_border_pixels(&pixmap(5, 4, &[(2, 2, YELLOW)])));
+    }
+
+    #[test]
+    fn test_partial_bounds() {
+        // only the top and left edges have markers
+        let pixels = [(0, 0, YELLOW), (1, 0, PINK), (2, 0, PINK), (0, 1, YELLOW)];
+        let bounds = parse_reaper_bounds(&pixmap(6, 5, &pixels)).unwrap();
+        assert_eq!(bounds.sides, vec![Side::Top, Side::Left]);
+        assert_eq!((bounds.pink.l, bounds.pink.t), (2, 0));
+        assert_eq!((bounds.yellow.l, bounds.yellow.t), (0, 1));
+        assert_eq!((bounds.pink.r, bounds.pink.b), (0, 0));
+
+        // only the bottom and right edges
+        let bounds = parse_reaper_bounds(&pixmap(6, 5, &[(5, 4, PINK), (4, 4, PINK)])).unwrap();
+        assert_eq!(bounds.sides, vec![Side::Bottom, Side::Right]);
+        assert_eq!(bounds.pink.r, 1);
+
+        // a lone corner pixel isn't enough to make the image bordered
+        assert_eq!(
+            parse_reaper_bounds(&pixmap(6, 5, &[(0, 0, PINK)])).unwrap_err(),
+            BoundsError::InvalidPixel {
+                side: Side::Bottom,
+                x: 5,
+                y: 4,
+                color: Color::RGBA(0, 0, 0, 0),
+                rule: BoundsRule::MissingCorner,
+            }
+        );
+
+        // a side with markers must still be valid
+        let pixels = [(0, 0, PINK), (5, 4, PINK), (5, 2, YELLOW)];
+        assert_eq!(
+            parse_reaper_bounds(&pixmap(6, 5, &pixels))
+                .unwrap_err()
+                .to_string(),
+            "right edge, pixel (5, 2) is #ffff00: pink or yellow can't come after a transparent gap"
+        );
+    }
+
+    #[test]
+    fn test_scale() {
+        let bounds = Bounds {
+            l: 3,
+            r: 1,
+            t: 3,
+            b: 0,
+        };
+        let scaled = bounds.scale(1.5, 2.0, Rounding::Ceil);
+        assert_eq!((scaled.l, scaled.r, scaled.t, scaled.b), (5, 2, 6, 0));
+        let scaled = bounds.scale(1.5, 0.5, Rounding::Round);
+        assert_eq!((scaled.l, scaled.r, scaled.t, scaled.b), (5, 2, 2, 0));
+        let scaled = bounds.scale(1.5, 0.1, Rounding::Floor);
+        assert_eq!((scaled.l, scaled.r, scaled.t, scaled.b), (4, 1, 1, 0));
+
+        assert!(bounds.fits(4, 3));
+        assert!(!bounds.fits(4, 2));
+        // each bound is inside the image, but they overlap
+        assert!(!bounds.fits(3, 3));
+
+        // rounding up both halves of an odd size makes them overlap
+        let halves = Bounds {
+            l: 5,
+            r: 5,
+            t: 0,
+            b: 0,
+        };
+        assert!(halves.fits(10, 1));
+        assert!(!halves.scale(1.5, 1.5, Rounding::Ceil).fits(15, 1));
+        assert!(halves.scale(1.5, 1.5, Rounding::Floor).fits(15, 1));
+    }
+
+    #[test]
+    fn test_metadata_bounds() {
+        let svg = r#"<?xml version="1.0"?>
+            <svg width="10" height="10" data-reaper-pink="4, 0, 4, 0"
+                 data-reaper-yellow='1,1,1,1'><rect data-reaper-pink="1,1,1,1"/></svg>"#;
+        let bounds = metadata_bounds(svg).unwrap().unwrap();
+        assert_eq!(bounds.pink, Bounds::parse("4,0,4,0").unwrap());
+        assert_eq!(bounds.yellow, Bounds::parse("1,1,1,1").unwrap());
+        assert_eq!(bounds.sides.len(), 4);
+
+        let bounds = metadata_bounds(r#"<svg data-reaper-pink="2,0,0,0">"#).unwrap();
+        assert!(bounds.unwrap().yellow.is_empty());
+        assert_eq!(
+            metadata_bounds(r#"<svg width="1"><g/></svg>"#).unwrap(),
+            None
+        );
+        assert_eq!(
+            metadata_bounds(r#"<svg data-reaper-pink="1,2,3">"#).unwrap_err(),
+            BoundsError::InvalidMetadata("data-reaper-pink", "1,2,3".into())
+        );
+
+        // only the attributes of the root element count, not markup that looks like it
+        let svg = r#"<?xml version="1.0"?><?svg data-reaper-pink="9,9,9,9"?>
+            <!DOCTYPE svg [ <!ENTITY e "<svg data-reaper-pink='9,9,9,9'>"> ]>
+            <!-- <svg data-reaper-pink="9,9,9,9"> -->
+            <svg aria-label="a > b" data-reaper-pink="1,0,1,0"/>"#;
+        let bounds = metadata_bounds(svg).unwrap().unwrap();
+        assert_eq!(bounds.pink, Bounds::parse("1,0,1,0").unwrap());
+        assert_eq!(
+            metadata_bounds(r#"<!-- <svg data-reaper-pink="1,0,1,0"> --><svg/>"#).unwrap(),
+            None
+        );
+        assert_eq!(
+            metadata_bounds(r#"<svg><g data-reaper-pink="1,0,1,0"/></svg>"#).unwrap(),
+            None
+        );
+    }
 }
@@ -0,0 +1,268 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{
+    bounds::{ReaperBounds, Rounding},
+    render::UpscaleMode,
+};
+
+/// Name of the cache file written next to the outputs in each output directory
+const CACHE_FILE_NAME: &str = ".svgthing-cache";
+
+/// Hash of everything that affects the contents of a rendered output. `fonts` is the
+/// `fonts_fingerprint` of the fonts directory.
+pub fn output_key(
+    text: &str,
+    mode: &UpscaleMode,
+    scale: f32,
+    rounding: Rounding,
+    borders: Option<&ReaperBounds>,
+    fonts: u64,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    text.hash(&mut hasher);
+    mode.hash(&mut hasher);
+    scale.to_bits().hash(&mut hasher);
+    rounding.hash(&mut hasher);
+    borders.hash(&mut hasher);
+    fonts.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn add_font_files(path: &Path, files: &mut Vec<(PathBuf, u64, Option<SystemTime>)>) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            add_font_files(&entry.path(), files);
+        }
+    } else {
+        files.push((path.to_owned(), metadata.len(), metadata.modified().ok()));
+    }
+}
+
+/// Hash of the paths, sizes and modification times of the files in the fonts directory,
+/// so that outputs are re-rendered when fonts are added, removed or changed
+pub fn fonts_fingerprint(fonts_dir: Option<&Path>) -> u64 {
+    let mut files = Vec::new();
+    if let Some(dir) = fonts_dir {
+        add_font_files(dir, &mut files);
+    }
+    files.sort();
+
+    let mut hasher = DefaultHasher::new();
+    files.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Default)]
+struct CacheDir {
+    /// Maps output file names to the key they were last rendered with
+    entries: HashMap<String, u64>,
+    modified: bool,
+}
+
+impl CacheDir {
+    fn load(dir: &Path) -> Self {
+        // a missing or corrupted cache just means everything gets re-rendered
+        let Ok(text) = fs::read_to_string(dir.join(CACHE_FILE_NAME)) else {
+            return Self::default();
+        };
+
+        let entries = text
+            .lines()
+            .filter_map(|line| {
+                let (key, name) = line.split_once(' ')?;
+                let key = u64::from_str_radix(key, 16).ok()?;
+                Some((name.to_owned(), key))
+            })
+            .collect();
+
+        Self {
+            entries,
+            modified: false,
+        }
+    }
+
+    fn save(&self, dir: &Path) -> io::Result<()> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort();
+
+        let text: String = entries
+            .into_iter()
+            .map(|(name, key)| format!("{:016x} {}\n", key, name))
+            .collect();
+        fs::write(dir.join(CACHE_FILE_NAME), text)
+    }
+}
+
+/// Keeps track of which outputs are up to date, so unchanged outputs can be skipped
+#[derive(Default)]
+pub struct OutputCache {
+    dirs: Mutex<HashMap<PathBuf, CacheDir>>,
+}
+
+fn split_output_path(output: &Path) -> Option<(PathBuf, String)> {
+    let dir = output.parent()?.to_owned();
+    let name = output.file_name()?.to_str()?.to_owned();
+    Some((dir, name))
+}
+
+impl OutputCache {
+    /// Return true if the output exists and was rendered with the same key
+    pub fn is_fresh(&self, output: &Path, key: u64) -> bool {
+        let Some((dir, name)) = split_output_path(output) else {
+            return false;
+        };
+        if !output.exists() {
+            return false;
+        }
+
+        let mut dirs = self.dirs.lock().unwrap();
+        let cache_dir = dirs
+            .entry(dir)
+            .or_insert_with_key(|dir| CacheDir::load(dir));
+        cache_dir.entries.get(&name) == Some(&key)
+    }
+
+    /// Record that an output has been rendered with the given key
+    pub fn update(&self, output: &Path, key: u64) {
+        let Some((dir, name)) = split_output_path(output) else {
+            return;
+        };
+
+        let mut dirs = self.dirs.lock().unwrap();
+        let cache_dir = dirs
+            .entry(dir)
+            .or_insert_with_key(|dir| CacheDir::load(dir));
+        cache_dir.entries.insert(name, key);
+        cache_dir.modified = true;
+    }
+
+    /// Write the cache files of all modified output directories
+    pub fn save(&self) -> io::Result<()> {
+        let dirs = self.dirs.lock().unwrap();
+        for (dir, cache_dir) in dirs.iter() {
+            if cache_dir.modified {
+                cache_dir.save(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_key() {
+        let key = output_key("<svg/>", &UpscaleMode::Normal, 1.5, Rounding::Ceil, None, 0);
+        assert_eq!(
+            key,
+            output_key("<svg/>", &UpscaleMode::Normal, 1.5, Rounding::Ceil, None, 0)
+        );
+        assert_ne!(
+            key,
+            output_key("<svg/>", &UpscaleMode::Normal, 2.0, Rounding::Ceil, None, 0)
+        );
+        assert_ne!(
+            key,
+            output_key(
+                "<svg/>",
+                &UpscaleMode::HORIZONTAL_BUTTON,
+                1.5,
+                Rounding::Ceil,
+                None,
+                0
+            )
+        );
+        assert_ne!(
+            key,
+            output_key(
+                "<svg></svg>",
+                &UpscaleMode::Normal,
+                1.5,
+                Rounding::Ceil,
+                None,
+                0
+            )
+        );
+        assert_ne!(
+            key,
+            output_key(
+                "<svg/>",
+                &UpscaleMode::Normal,
+                1.5,
+                Rounding::Floor,
+                None,
+                0
+            )
+        );
+        let borders = ReaperBounds::default();
+        assert_ne!(
+            key,
+            output_key(
+                "<svg/>",
+                &UpscaleMode::Normal,
+                1.5,
+                Rounding::Ceil,
+                Some(&borders),
+                0
+            )
+        );
+        assert_ne!(
+            key,
+            output_key("<svg/>", &UpscaleMode::Normal, 1.5, Rounding::Ceil, None, 1)
+        );
+    }
+
+    #[test]
+    fn test_fonts_fingerprint() {
+        let dir = std::env::temp_dir().join(format!("svgthing-test-fonts-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let empty = fonts_fingerprint(Some(&dir));
+        assert_eq!(empty, fonts_fingerprint(None));
+
+        // fonts in subdirectories count, and changing a font changes the fingerprint
+        fs::write(dir.join("sub/a.ttf"), b"a").unwrap();
+        let one = fonts_fingerprint(Some(&dir));
+        assert_ne!(one, empty);
+        assert_eq!(one, fonts_fingerprint(Some(&dir)));
+        fs::write(dir.join("sub/a.ttf"), b"ab").unwrap();
+        assert_ne!(fonts_fingerprint(Some(&dir)), one);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = std::env::temp_dir().join("svgthing-test-cache-roundtrip");
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("button.png");
+        fs::write(&output, b"").unwrap();
+
+        let cache = OutputCache::default();
+        assert!(!cache.is_fresh(&output, 123));
+        cache.update(&output, 123);
+        cache.save().unwrap();
+
+        let cache = OutputCache::default();
+        assert!(cache.is_fresh(&output, 123));
+        assert!(!cache.is_fresh(&output, 456));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
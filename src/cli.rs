@@ -1,7 +1,10 @@
 use std::{num::NonZeroU32, path::PathBuf};
 
-use crate::parser::Color;
-use bpaf::Bpaf;
+use crate::{
+    bounds::{ReaperBounds, Rounding},
+    parser::Color,
+};
+use bpaf::{Bpaf, Parser};
 
 #[derive(Debug, Clone, Bpaf)]
 #[bpaf(options)]
@@ -10,8 +13,31 @@ pub enum Options {
     #[bpaf(command)]
     Colors {
         count: bool,
-        /// Parse alpha values when parsing the SVG
+        /// List the files, lines and columns where each color is used, along with the id of
+        /// the element using it
+        locations: bool,
+        /// Only list where this color is used
+        #[bpaf(long("where"), argument("COLOR"), optional)]
+        filter: Option<Color>,
+        /// Group colors that are perceptually within this CIEDE2000 difference of each
+        /// other, e.g. 2. The most used color of each group is suggested to replace the rest
+        #[bpaf(long("cluster"), argument("DELTA_E"), optional)]
+        cluster: Option<f32>,
+        /// Output format: text, hex, json, csv, gpl (GIMP palette), css or toml (a palette
+        /// for --palette)
+        #[bpaf(
+            long("format"),
+            argument::<String>("FORMAT"),
+            parse(parse_color_format),
+            fallback(ColorFormat::Text)
+        )]
+        format: ColorFormat,
+        /// Parse alpha values when parsing the SVG, including opacity properties such as
+        /// stroke-opacity and element opacity
         include_alpha: bool,
+        /// Only parse colors in paint properties such as fill and stroke, instead of
+        /// anywhere in the SVG's text
+        paint_only: bool,
         #[bpaf(positional("PATH"))]
         paths: Vec<PathBuf>,
     },
@@ -23,8 +49,29 @@ pub enum Options {
         all_input_colors: bool,
         /// Assert that all SVG colors appear in the input colors
         all_svg_colors: bool,
-        /// Parse alpha values when parsing the SVG
+        /// Parse alpha values when parsing the SVG, including opacity properties such as
+        /// stroke-opacity and element opacity
         include_alpha: bool,
+        /// Only parse colors in paint properties such as fill and stroke, instead of
+        /// anywhere in the SVG's text
+        paint_only: bool,
+        /// Palette file naming the colors that mapping files refer to
+        #[bpaf(argument("FILE"))]
+        palette: Option<PathBuf>,
+        #[bpaf(external(hsl_transform), group_help("Color transforms"))]
+        transform: HslTransform,
+        #[bpaf(external(tolerance), optional)]
+        tolerance: Option<Tolerance>,
+        /// How scaled REAPER bounds are rounded to whole pixels: ceil (default), round or
+        /// floor
+        #[bpaf(
+            argument::<String>("MODE"),
+            parse(parse_rounding),
+            fallback(Rounding::Ceil)
+        )]
+        bounds_rounding: Rounding,
+        #[bpaf(external(batch_options))]
+        batch: BatchOptions,
         #[bpaf(external(render_task), some("at least one task must be specified"))]
         tasks: Vec<RenderTask>,
     },
@@ -36,34 +83,249 @@ pub enum Options {
         all_input_colors: bool,
         /// Assert that all SVG colors appear in the input colors
         all_svg_colors: bool,
-        /// Parse alpha values when parsing the SVG
+        /// Parse alpha values when parsing the SVG, including opacity properties such as
+        /// stroke-opacity and element opacity
         include_alpha: bool,
+        /// Only parse colors in paint properties such as fill and stroke, instead of
+        /// anywhere in the SVG's text
+        paint_only: bool,
+        /// Palette file naming the colors that mapping files refer to
+        #[bpaf(argument("FILE"))]
+        palette: Option<PathBuf>,
+        #[bpaf(external(hsl_transform), group_help("Color transforms"))]
+        transform: HslTransform,
+        #[bpaf(external(tolerance), optional)]
+        tolerance: Option<Tolerance>,
+        /// How scaled REAPER bounds are rounded to whole pixels: ceil (default), round or
+        /// floor
+        #[bpaf(
+            argument::<String>("MODE"),
+            parse(parse_rounding),
+            fallback(Rounding::Ceil)
+        )]
+        bounds_rounding: Rounding,
         #[bpaf(external(stdin_render_task))]
         task: StdinRenderTask,
     },
+    /// Map the colors of SVG files and write the recolored SVGs, instead of rendering them
+    #[bpaf(command)]
+    Recolor {
+        /// Assert that all input colors are used in the SVG
+        all_input_colors: bool,
+        /// Assert that all SVG colors appear in the input colors
+        all_svg_colors: bool,
+        /// Parse alpha values when parsing the SVG, including opacity properties such as
+        /// stroke-opacity and element opacity
+        include_alpha: bool,
+        /// Only parse colors in paint properties such as fill and stroke, instead of
+        /// anywhere in the SVG's text
+        paint_only: bool,
+        /// Palette file naming the colors that mapping files refer to
+        #[bpaf(argument("FILE"))]
+        palette: Option<PathBuf>,
+        #[bpaf(external(hsl_transform), group_help("Color transforms"))]
+        transform: HslTransform,
+        #[bpaf(external(tolerance), optional)]
+        tolerance: Option<Tolerance>,
+        /// Replace colors in the SVGs with new colors
+        #[bpaf(external(color_mapping), many)]
+        color_mappings: Vec<ColorMapping>,
+        /// Read color mappings from a TOML or JSON file mapping source colors to palette
+        /// names or colors, e.g. '"#ff0000" = "accent"'. Mappings from -m take priority
+        #[bpaf(long("mappings"), argument("FILE"), many)]
+        mapping_files: Vec<PathBuf>,
+        #[bpaf(external(recolor_output))]
+        output: RecolorOutput,
+        /// SVG files, directories of SVGs or glob patterns to recolor
+        #[bpaf(positional("SVG"), some("at least one SVG must be specified"))]
+        inputs: Vec<PathBuf>,
+    },
+    /// Check SVGs for fractional sizes, tiles that don't divide evenly, malformed REAPER
+    /// borders, pink/yellow used in the artwork and colors outside a palette, without
+    /// rendering any PNGs. Exits with an error if any problems are found
+    #[bpaf(command)]
+    Lint {
+        fonts: Option<PathBuf>,
+        /// Parse alpha values when parsing the SVG, including opacity properties such as
+        /// stroke-opacity and element opacity
+        include_alpha: bool,
+        /// Only parse colors in paint properties such as fill and stroke, instead of
+        /// anywhere in the SVG's text
+        paint_only: bool,
+        /// Report colors that aren't in this palette file
+        #[bpaf(argument("FILE"))]
+        palette: Option<PathBuf>,
+        /// Check the SVGs of a build manifest, using the tile settings of each file
+        #[bpaf(argument("FILE"))]
+        manifest: Option<PathBuf>,
+        #[bpaf(external(tile_setting), optional, group_help("Tiled upscaling"))]
+        tile_setting: Option<TileSetting>,
+        /// Output format: text or json
+        #[bpaf(
+            long("format"),
+            argument::<String>("FORMAT"),
+            parse(parse_lint_format),
+            fallback(LintFormat::Text)
+        )]
+        format: LintFormat,
+        /// SVG files, directories of SVGs or glob patterns to check
+        #[bpaf(positional("SVG"), many)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Render all SVG files described in a TOML or JSON build manifest
+    #[bpaf(command)]
+    Build {
+        /// Override the fonts directory specified in the manifest
+        fonts: Option<PathBuf>,
+        #[bpaf(external(batch_options))]
+        batch: BatchOptions,
+        #[bpaf(positional("MANIFEST"))]
+        manifest: PathBuf,
+    },
 }
 
+/// Options for rendering many tasks at once
 #[derive(Debug, Clone, Bpaf)]
-#[bpaf(options)]
-pub struct RenderTasks {
-    #[bpaf(external(render_task), some("at least one task must be specified"))]
-    pub tasks: Vec<RenderTask>,
+pub struct BatchOptions {
+    /// Number of SVGs to render in parallel, defaults to the number of CPUs
+    #[bpaf(short, long, argument("N"))]
+    pub jobs: Option<usize>,
+    /// Re-render all outputs, even if they are up to date
+    pub force: bool,
+    /// Keep running and re-render SVGs when they change. Directories and glob patterns are
+    /// expanded once at startup, so SVGs added later are not rendered until the command is
+    /// restarted or the manifest, palette or mapping files change
+    pub watch: bool,
+    #[bpaf(external(failure_mode), fallback(FailureMode::KeepGoing))]
+    pub failure_mode: FailureMode,
+    #[bpaf(external(theme_zip_options), optional)]
+    pub zip: Option<ThemeZipOptions>,
 }
 
+/// Where the `recolor` command writes SVGs
 #[derive(Debug, Clone, Bpaf)]
-#[bpaf(adjacent)]
+pub enum RecolorOutput {
+    Dir {
+        /// Write the recolored SVGs into this directory, mirroring the tree of input
+        /// directories and glob patterns
+        #[bpaf(long("output-dir"), argument("DIR"))]
+        dir: PathBuf,
+    },
+    InPlace {
+        /// Overwrite the input SVGs
+        #[bpaf(long("in-place"))]
+        #[allow(dead_code)]
+        in_place: (),
+        /// Keep a copy of each input SVG, with this suffix added to its file name, e.g. .bak
+        #[bpaf(long("backup"), argument("SUFFIX"), optional)]
+        backup: Option<String>,
+    },
+}
+
+/// Options for packaging the rendered images as a REAPER theme
+#[derive(Debug, Clone, Bpaf)]
+pub struct ThemeZipOptions {
+    /// Write the rendered images into a .ReaperThemeZip archive instead of the output paths.
+    /// Only the file names of the outputs are used, images with a scale other than 1
+    /// are placed in hi-DPI folders such as 150/ and 200/
+    #[bpaf(long("zip"), argument("ZIP"))]
+    pub path: PathBuf,
+    /// Name of the theme in the archive, defaults to the file name of the archive
+    #[bpaf(long("theme-name"), argument("NAME"))]
+    pub name: Option<String>,
+    /// .ReaperTheme file to include in the archive
+    #[bpaf(long("theme-file"), argument("FILE"))]
+    pub theme_file: Option<PathBuf>,
+    /// rtconfig.txt to include in the archive
+    #[bpaf(long("rtconfig"), argument("FILE"))]
+    pub rtconfig: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+pub enum FailureMode {
+    /// Keep rendering the remaining SVGs when one fails (default)
+    #[bpaf(long("keep-going"))]
+    KeepGoing,
+    /// Stop rendering as soon as an SVG fails
+    #[bpaf(long("fail-fast"))]
+    FailFast,
+}
+
+/// Output format of the `colors` command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorFormat {
+    Text,
+    Hex,
+    Json,
+    Csv,
+    Gpl,
+    Css,
+    Toml,
+}
+
+fn parse_color_format(text: String) -> Result<ColorFormat, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "text" => Ok(ColorFormat::Text),
+        "hex" => Ok(ColorFormat::Hex),
+        "json" => Ok(ColorFormat::Json),
+        "csv" => Ok(ColorFormat::Csv),
+        "gpl" => Ok(ColorFormat::Gpl),
+        "css" => Ok(ColorFormat::Css),
+        "toml" => Ok(ColorFormat::Toml),
+        _ => Err(format!(
+            "unknown format {:?}, expected text, hex, json, csv, gpl, css or toml",
+            text
+        )),
+    }
+}
+
+/// Output format of the `lint` command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LintFormat {
+    Text,
+    Json,
+}
+
+fn parse_lint_format(text: String) -> Result<LintFormat, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "text" => Ok(LintFormat::Text),
+        "json" => Ok(LintFormat::Json),
+        _ => Err(format!("unknown format {:?}, expected text or json", text)),
+    }
+}
+
+fn has_outputs(task: &RenderTask) -> bool {
+    !task.outputs.is_empty() || task.output_template.is_some()
+}
+
+fn no_borders() -> impl Parser<Option<ReaperBounds>> {
+    bpaf::pure(None)
+}
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(adjacent, guard(has_outputs, "at least one output must be specified"))]
 pub struct RenderTask {
-    /// Input path of the SVG to be rendered
-    #[bpaf(short, long, argument("SVG"))]
+    /// Input path of the SVG to be rendered. A directory or a glob pattern such as
+    /// 'svg/**/*.svg' renders every matching SVG with the same settings (alias: --input-dir)
+    #[bpaf(short, long, long("input-dir"), argument("SVG"))]
     pub input: PathBuf,
     /// Replace colors in the input SVG with new colors
     #[bpaf(external(color_mapping), many)]
     pub color_mappings: Vec<ColorMapping>,
+    /// Read color mappings from a TOML or JSON file mapping source colors to palette
+    /// names or colors, e.g. '"#ff0000" = "accent"'. Mappings from -m take priority
+    #[bpaf(long("mappings"), argument("FILE"), many)]
+    pub mapping_files: Vec<PathBuf>,
     #[bpaf(external(tile_setting), optional, group_help("Tiled upscaling"))]
     pub tile_setting: Option<TileSetting>,
     /// The output PNBs to render
-    #[bpaf(external(output), some("at least one output must be specified"))]
+    #[bpaf(external(output), many)]
     pub outputs: Vec<Output>,
+    #[bpaf(external(output_template), optional)]
+    pub output_template: Option<OutputTemplate>,
+    /// A border to add around the SVG, only set by build manifests
+    #[bpaf(external(no_borders))]
+    pub borders: Option<ReaperBounds>,
 }
 
 #[derive(Debug, Clone, Bpaf)]
@@ -72,6 +334,10 @@ pub struct StdinRenderTask {
     /// Replace colors in the input SVG with new colors
     #[bpaf(external(color_mapping), many)]
     pub color_mappings: Vec<ColorMapping>,
+    /// Read color mappings from a TOML or JSON file mapping source colors to palette
+    /// names or colors, e.g. '"#ff0000" = "accent"'. Mappings from -m take priority
+    #[bpaf(long("mappings"), argument("FILE"), many)]
+    pub mapping_files: Vec<PathBuf>,
     #[bpaf(external(tile_setting), optional, group_help("Tiled upscaling"))]
     pub tile_setting: Option<TileSetting>,
     /// The output PNBs to render
@@ -84,6 +350,7 @@ pub struct StdinRenderTask {
 pub struct ColorMapping {
     /// Map a color to a new color
     #[bpaf(short, long)]
+    #[allow(dead_code)]
     pub map: (),
     /// Color to map from. If this color isn't found in the SVG, this will raise an error
     #[bpaf(positional("FROM_COLOR"))]
@@ -99,11 +366,120 @@ pub struct Output {
     /// Output path to save the rendered image (should be PNG format)
     #[bpaf(short, long, argument("OUTPUT"))]
     pub output: PathBuf,
-    /// Scale to render the image
+    /// Scale to render the image, values below 1 downscale the image
     #[bpaf(short, long, fallback(1.0), argument("SCALE"))]
     pub scale: f32,
 }
 
+fn parse_hue_range(text: String) -> Result<(f32, f32), String> {
+    let (from, to) = text
+        .split_once("..")
+        .ok_or_else(|| format!("invalid hue range {:?}, expected FROM..TO", text))?;
+    let parse = |hue: &str| {
+        hue.trim()
+            .parse::<f32>()
+            .map_err(|err| format!("invalid hue {:?}: {}", hue, err))
+    };
+    Ok((parse(from)?, parse(to)?))
+}
+
+/// Adjustments applied to every non-reserved color that isn't mapped explicitly
+#[derive(Debug, Clone, Bpaf)]
+pub struct HslTransform {
+    /// Rotate the hue of colors by this many degrees
+    #[bpaf(long("hue-rotate"), argument("DEGREES"), fallback(0.0))]
+    pub hue_rotate: f32,
+    /// Multiply the saturation of colors by this factor
+    #[bpaf(long("saturation"), argument("SCALE"), fallback(1.0))]
+    pub saturation: f32,
+    /// Add this many percentage points to the lightness of colors, e.g. -10 to darken them
+    #[bpaf(long("lightness"), argument("OFFSET"), fallback(0.0))]
+    pub lightness: f32,
+    /// Only transform colors with a hue in this range of degrees, e.g. '90..150'.
+    /// The range wraps around when FROM is larger than TO, e.g. '330..30'
+    #[bpaf(
+        long("hue-range"),
+        argument::<String>("FROM..TO"),
+        parse(parse_hue_range),
+        optional
+    )]
+    pub hue_range: Option<(f32, f32)>,
+}
+
+/// How far an SVG color may be from the source color of a mapping and still be mapped
+#[derive(Debug, Clone, Copy, PartialEq, Bpaf)]
+pub enum Tolerance {
+    Channel {
+        /// Also map colors whose channels each differ by at most N from a mapping's source
+        /// color
+        #[bpaf(long("tolerance"), argument("N"))]
+        max_difference: u8,
+    },
+    DeltaE {
+        /// Also map colors within this CIEDE2000 difference of a mapping's source color,
+        /// e.g. 1
+        #[bpaf(long("tolerance-delta-e"), argument("DELTA_E"))]
+        max_delta_e: f32,
+    },
+}
+
+fn parse_rounding(text: String) -> Result<Rounding, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "ceil" => Ok(Rounding::Ceil),
+        "round" => Ok(Rounding::Round),
+        "floor" => Ok(Rounding::Floor),
+        _ => Err(format!(
+            "unknown rounding {:?}, expected ceil, round or floor",
+            text
+        )),
+    }
+}
+
+fn parse_scales(text: String) -> Result<Vec<f32>, String> {
+    text.split(',')
+        .map(|scale| {
+            scale
+                .trim()
+                .parse::<f32>()
+                .map_err(|err| format!("invalid scale {:?}: {}", scale, err))
+        })
+        .collect()
+}
+
+/// Outputs generated from a path template, one for each scale
+#[derive(Debug, Clone, Bpaf)]
+pub struct OutputTemplate {
+    #[bpaf(external(output_pattern))]
+    pub pattern: OutputPattern,
+    /// Comma-separated scales to render the output template at, e.g. '1,1.5,2'
+    #[bpaf(
+        long("scales"),
+        argument::<String>("SCALES"),
+        parse(parse_scales),
+        fallback(vec![1.0])
+    )]
+    pub scales: Vec<f32>,
+    /// Variant name to use for the {variant} placeholder
+    #[bpaf(long("variant"), argument("NAME"))]
+    pub variant: Option<String>,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+pub enum OutputPattern {
+    Template {
+        /// Output path template, e.g. '{dir}/{scale_pct}/{stem}.png'.
+        /// Placeholders: {dir}, {rel_dir}, {stem}, {scale}, {scale_pct}, {variant}
+        #[bpaf(long("out"), argument("TEMPLATE"))]
+        template: String,
+    },
+    Dir {
+        /// Mirror the input directory tree into this directory as PNGs. Outputs with a scale
+        /// other than 1 are placed in hi-DPI folders such as DIR/150/
+        #[bpaf(long("output-dir"), argument("DIR"))]
+        dir: PathBuf,
+    },
+}
+
 #[derive(Debug, Clone, Bpaf)]
 pub enum TileSetting {
     /// Image contains 3 equal-sized tiles placed horizontally, i.e. a horizontally-sliced button.
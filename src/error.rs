@@ -0,0 +1,46 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::{
+    bounds::BoundsError, inputs::InputError, manifest::ManifestError, palette::PaletteError,
+    render::UpscaleError, template::TemplateError,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("failed to read {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("failed to read stdin: {0}")]
+    ReadStdin(io::Error),
+    #[error("failed to write {0}: {1}")]
+    Write(PathBuf, String),
+    #[error("failed to create directory {0}: {1}")]
+    CreateDir(PathBuf, io::Error),
+    #[error("failed to parse colors: {0}")]
+    ParseColors(String),
+    #[error("failed to map colors: {0}")]
+    MapColors(String),
+    #[error("failed to parse svg: {0}")]
+    ParseSvg(#[from] resvg::usvg::Error),
+    #[error(transparent)]
+    Upscale(#[from] UpscaleError),
+    #[error(transparent)]
+    Bounds(#[from] BoundsError),
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error(transparent)]
+    Manifest(#[from] ManifestError),
+    #[error(transparent)]
+    Palette(#[from] PaletteError),
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+    #[error("failed to write theme zip: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("multiple outputs are written to {0} in the theme zip")]
+    DuplicateArchiveEntry(String),
+    #[error("{0} of {1} tasks failed")]
+    TasksFailed(usize, usize),
+    #[error("found {0} problems in {1} SVGs")]
+    LintFailed(usize, usize),
+}
@@ -0,0 +1,144 @@
+use crate::{cli::HslTransform, parser::Color};
+
+/// Convert RGB to HSL, with the hue in degrees and the saturation and lightness in 0..=1
+pub fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (h * 60.0, s, l)
+}
+
+/// Convert HSL to RGB, the hue wraps around and the saturation and lightness are clamped
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let s = s.clamp(0.0, 1.0);
+    let l = l.clamp(0.0, 1.0);
+
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+impl Default for HslTransform {
+    fn default() -> Self {
+        Self {
+            hue_rotate: 0.0,
+            saturation: 1.0,
+            lightness: 0.0,
+            hue_range: None,
+        }
+    }
+}
+
+impl HslTransform {
+    pub fn is_identity(&self) -> bool {
+        self.hue_rotate.rem_euclid(360.0) == 0.0 && self.saturation == 1.0 && self.lightness == 0.0
+    }
+
+    fn in_hue_range(&self, h: f32, s: f32) -> bool {
+        match self.hue_range {
+            // greys don't have a hue, so they never fall in a hue range
+            Some(_) if s == 0.0 => false,
+            Some((from, to)) if from <= to => (from..=to).contains(&h),
+            // the range wraps around, e.g. 330..30 for reds
+            Some((from, to)) => h >= from || h <= to,
+            None => true,
+        }
+    }
+
+    /// Transform a color, keeping its alpha. REAPER's reserved colors are never produced,
+    /// results that land on them are nudged by one step in the green channel.
+    pub fn apply(&self, color: &Color) -> Color {
+        let (h, s, l) = rgb_to_hsl(color.r(), color.g(), color.b());
+        if !self.in_hue_range(h, s) {
+            return color.clone();
+        }
+
+        let (r, g, b) = hsl_to_rgb(
+            h + self.hue_rotate,
+            s * self.saturation,
+            l + self.lightness / 100.0,
+        );
+        let mut result = Color::RGB(r, g, b);
+        if result.is_reaper_reserved() {
+            result = Color::RGB(r, g ^ 1, b);
+        }
+        match color.a() {
+            Some(a) => result.with_a(a),
+            None => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(hue_rotate: f32, hue_range: Option<(f32, f32)>) -> HslTransform {
+        HslTransform {
+            hue_rotate,
+            saturation: 1.0,
+            lightness: 0.0,
+            hue_range,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for (r, g, b) in [(0x66, 0x99, 0xcc), (255, 0, 0), (30, 30, 30), (12, 200, 97)] {
+            let (h, s, l) = rgb_to_hsl(r, g, b);
+            assert_eq!(hsl_to_rgb(h, s, l), (r, g, b));
+        }
+        assert_eq!(rgb_to_hsl(0, 0, 255), (240.0, 1.0, 0.5));
+    }
+
+    #[test]
+    fn test_apply() {
+        let blue = Color::RGBA(0, 0, 255, 128);
+        assert_eq!(
+            transform(120.0, None).apply(&blue),
+            Color::RGBA(255, 0, 0, 128)
+        );
+
+        // only greens are rotated
+        let greens = transform(120.0, Some((90.0, 150.0)));
+        assert_eq!(greens.apply(&blue), blue);
+        assert_eq!(greens.apply(&Color::RGB(0, 255, 0)), Color::RGB(0, 0, 255));
+
+        // wrapping range, greys are never in range
+        let reds = transform(180.0, Some((330.0, 30.0)));
+        assert_eq!(reds.apply(&Color::RGB(255, 0, 0)), Color::RGB(0, 255, 255));
+        assert_eq!(reds.apply(&Color::RGB(30, 30, 30)), Color::RGB(30, 30, 30));
+
+        // never produces the reserved pink
+        let pink = transform(60.0, None).apply(&Color::RGB(0, 0, 255));
+        assert_eq!(pink, Color::RGB(255, 1, 255));
+    }
+}
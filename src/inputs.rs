@@ -0,0 +1,156 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InputError {
+    #[error("invalid glob pattern {0:?}: {1}")]
+    Pattern(String, glob::PatternError),
+    #[error("failed to expand glob pattern: {0}")]
+    Glob(#[from] glob::GlobError),
+    #[error("failed to read directory {0}: {1}")]
+    ReadDir(PathBuf, io::Error),
+    #[error("{0:?} did not match any SVG files")]
+    NoMatches(String),
+}
+
+/// An input SVG found by expanding an input path
+#[derive(Debug, PartialEq)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// Directory of the SVG relative to the input directory or the base of the glob pattern,
+    /// used to mirror the input tree in the outputs
+    pub rel_dir: PathBuf,
+}
+
+fn is_pattern(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+}
+
+/// Leading components of a glob pattern that don't contain any wildcards
+fn pattern_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|component| match component {
+            Component::Normal(name) => !is_pattern(&name.to_string_lossy()),
+            _ => true,
+        })
+        .collect()
+}
+
+fn walk_dir(dir: &Path, result: &mut Vec<PathBuf>) -> Result<(), InputError> {
+    let entries = fs::read_dir(dir).map_err(|err| InputError::ReadDir(dir.to_owned(), err))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|err| InputError::ReadDir(dir.to_owned(), err))?
+            .path();
+        if path.is_dir() {
+            walk_dir(&path, result)?;
+        } else if is_svg(&path) {
+            result.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn input_file(path: PathBuf, base: &Path) -> InputFile {
+    let rel_dir = path
+        .parent()
+        .and_then(|dir| dir.strip_prefix(base).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    InputFile { path, rel_dir }
+}
+
+/// Expand an input path into the SVGs it refers to. The input may be a single file,
+/// a directory which is searched recursively for SVGs, or a glob pattern.
+pub fn expand_input(input: &Path) -> Result<Vec<InputFile>, InputError> {
+    let text = input.to_string_lossy();
+
+    let (base, mut paths) = if is_pattern(&text) {
+        let paths = glob::glob(&text)
+            .map_err(|err| InputError::Pattern(text.clone().into_owned(), err))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect();
+        (pattern_base(input), paths)
+    } else if input.is_dir() {
+        let mut paths = Vec::new();
+        walk_dir(input, &mut paths)?;
+        (input.to_owned(), paths)
+    } else {
+        // plain files are passed through, even if they don't exist
+        return Ok(vec![InputFile {
+            path: input.to_owned(),
+            rel_dir: PathBuf::new(),
+        }]);
+    };
+
+    if paths.is_empty() {
+        return Err(InputError::NoMatches(text.into_owned()));
+    }
+
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| input_file(path, &base))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_base() {
+        assert_eq!(
+            pattern_base(Path::new("theme/svg/**/*.svg")),
+            PathBuf::from("theme/svg")
+        );
+        assert_eq!(pattern_base(Path::new("*.svg")), PathBuf::new());
+        assert_eq!(
+            pattern_base(Path::new("/abs/track_[ab]/x.svg")),
+            PathBuf::from("/abs")
+        );
+    }
+
+    #[test]
+    fn test_expand_dir() {
+        let dir = std::env::temp_dir().join("svgthing-test-expand-dir");
+        fs::create_dir_all(dir.join("tcp/buttons")).unwrap();
+        fs::write(dir.join("a.svg"), "").unwrap();
+        fs::write(dir.join("tcp/buttons/b.SVG"), "").unwrap();
+        fs::write(dir.join("tcp/notes.txt"), "").unwrap();
+
+        let inputs = expand_input(&dir).unwrap();
+        assert_eq!(
+            inputs,
+            vec![
+                InputFile {
+                    path: dir.join("a.svg"),
+                    rel_dir: PathBuf::new(),
+                },
+                InputFile {
+                    path: dir.join("tcp/buttons/b.SVG"),
+                    rel_dir: PathBuf::from("tcp/buttons"),
+                },
+            ]
+        );
+
+        let pattern = dir.join("tcp/*/*.SVG");
+        let inputs = expand_input(&pattern).unwrap();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].rel_dir, PathBuf::from("buttons"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
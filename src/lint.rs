@@ -0,0 +1,435 @@
+//! Checks for problems in REAPER theme SVGs that would fail or spoil a render, without
+//! writing any images
+
+use std::{fmt, path::Path};
+
+use resvg::{tiny_skia::Pixmap, usvg};
+use serde::Serialize;
+
+use crate::{
+    bounds::{has_border_pixels, metadata_bounds, parse_reaper_bounds, ReaperBounds},
+    cli::LintFormat,
+    map_colors::{get_color_locations, ColorLocation},
+    notation::to_hex,
+    palette::Palette,
+    parser::Color,
+    render::{render, tile_size, UpscaleMode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// The SVG can't be parsed, so nothing else is checked
+    InvalidSvg,
+    /// The view box isn't a whole number of pixels
+    FractionalSize,
+    /// The image can't be divided evenly into its tiles
+    TileCount,
+    /// The image has pink or yellow pixels on its edge, but they don't form valid borders
+    MalformedBorder,
+    /// REAPER's pink or yellow is used inside the image, where it isn't a border
+    ReservedColor,
+    /// A color that isn't in the allowed palette
+    UnknownColor,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::InvalidSvg => "invalid-svg",
+            Rule::FractionalSize => "fractional-size",
+            Rule::TileCount => "tile-count",
+            Rule::MalformedBorder => "malformed-border",
+            Rule::ReservedColor => "reserved-color",
+            Rule::UnknownColor => "unknown-color",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    pub rule: Rule,
+    pub message: String,
+    /// Where the offending color is written in the SVG
+    #[serde(flatten)]
+    pub location: Option<ColorLocation>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+pub struct LintOptions<'a> {
+    pub include_alpha: bool,
+    pub paint_only: bool,
+    /// Colors that the SVG may use, besides REAPER's reserved colors
+    pub palette: Option<&'a Palette>,
+}
+
+fn violation(rule: Rule, message: String) -> Violation {
+    Violation {
+        rule,
+        message,
+        location: None,
+    }
+}
+
+fn is_reserved_pixel(pixmap: &Pixmap, x: u32, y: u32) -> bool {
+    pixmap.pixel(x, y).is_some_and(|pixel| {
+        let color = Color::RGB(pixel.red(), pixel.green(), pixel.blue());
+        pixel.alpha() == 255 && color.is_reaper_reserved()
+    })
+}
+
+/// Check the rendered image: the borders, the tiles and reserved colors in the artwork.
+/// `inserted` is the border from the SVG's metadata, which is added around the image.
+fn lint_pixmap(
+    pixmap: &Pixmap,
+    mode: &UpscaleMode,
+    inserted: Option<&ReaperBounds>,
+    result: &mut Vec<Violation>,
+) {
+    let (width, height) = (pixmap.width(), pixmap.height());
+
+    let has_bounds = match inserted {
+        Some(bounds) => {
+            for (name, bounds) in [("yellow", &bounds.yellow), ("pink", &bounds.pink)] {
+                if !bounds.fits(width, height) {
+                    let message = format!(
+                        "{} bounds in the metadata overlap or don't fit inside the {}x{} image",
+                        name, width, height
+                    );
+                    result.push(violation(Rule::MalformedBorder, message));
+                }
+            }
+            false
+        }
+        None => {
+            let bounds = parse_reaper_bounds(pixmap);
+            if let Err(err) = &bounds {
+                if has_border_pixels(pixmap) {
+                    result.push(violation(Rule::MalformedBorder, err.to_string()));
+                }
+            }
+            bounds.is_ok()
+        }
+    };
+    // with an inserted border, the whole image is artwork
+    let on_edge = |x: u32, y: u32| {
+        inserted.is_none() && (x == 0 || y == 0 || x == width - 1 || y == height - 1)
+    };
+
+    // reserved pixels on the edge are either borders or reported as malformed borders
+    let reserved: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|(x, y)| !on_edge(*x, *y) && is_reserved_pixel(pixmap, *x, *y))
+        .collect();
+    if let Some((x, y)) = reserved.first() {
+        result.push(violation(
+            Rule::ReservedColor,
+            format!(
+                "{} pixels inside the image are REAPER's pink/yellow, the first at ({}, {})",
+                reserved.len(),
+                x,
+                y
+            ),
+        ));
+    }
+
+    let (inner_width, inner_height) = match has_bounds {
+        true => (width.saturating_sub(2), height.saturating_sub(2)),
+        false => (width, height),
+    };
+    if let Err(err) = tile_size(inner_width, inner_height, mode) {
+        result.push(violation(Rule::TileCount, err.to_string()));
+    }
+}
+
+/// Check an SVG, given its text. An SVG that can't be parsed is reported as a violation
+/// rather than an error, so that one broken file doesn't stop the others being checked.
+pub fn lint_svg(
+    text: &str,
+    fontdb: &usvg::fontdb::Database,
+    mode: &UpscaleMode,
+    opt: &LintOptions,
+) -> Vec<Violation> {
+    let tree = match usvg::Tree::from_str(text, &usvg::Options::default(), fontdb) {
+        Ok(tree) => tree,
+        Err(err) => return vec![violation(Rule::InvalidSvg, err.to_string())],
+    };
+    let mut result = Vec::new();
+
+    let inserted = metadata_bounds(text).unwrap_or_else(|err| {
+        result.push(violation(Rule::MalformedBorder, err.to_string()));
+        None
+    });
+
+    // the image can only be checked if it can be rendered
+    match render(&tree) {
+        Ok(pixmap) => lint_pixmap(&pixmap, mode, inserted.as_ref(), &mut result),
+        Err(err) => result.push(violation(Rule::FractionalSize, err.to_string())),
+    }
+
+    if let Some(palette) = opt.palette {
+        match get_color_locations(text, opt.include_alpha, opt.paint_only) {
+            Ok(locations) => {
+                for (color, location) in locations {
+                    if !color.is_reaper_reserved() && !palette.contains(&color) {
+                        result.push(Violation {
+                            rule: Rule::UnknownColor,
+                            message: format!("{} is not in the palette", to_hex(&color)),
+                            location: Some(location),
+                        });
+                    }
+                }
+            }
+            Err(err) => result.push(violation(Rule::InvalidSvg, err)),
+        }
+    }
+
+    result
+}
+
+#[derive(Serialize)]
+struct LintResult<'a> {
+    file: &'a Path,
+    #[serde(flatten)]
+    violation: &'a Violation,
+}
+
+/// Format the violations found by the `lint` command, grouped by file
+pub fn format_results<P: AsRef<Path>>(
+    results: &[(P, Vec<Violation>)],
+    format: LintFormat,
+) -> String {
+    match format {
+        LintFormat::Text => results
+            .iter()
+            .flat_map(|(path, violations)| {
+                violations
+                    .iter()
+                    .map(move |violation| format!("{}: {}\n", path.as_ref().display(), violation))
+            })
+            .collect(),
+        LintFormat::Json => {
+            let json: Vec<_> = results
+                .iter()
+                .flat_map(|(path, violations)| {
+                    violations.iter().map(|violation| LintResult {
+                        file: path.as_ref(),
+                        violation,
+                    })
+                })
+                .collect();
+            let mut result = serde_json::to_string_pretty(&json).unwrap();
+            result.push('\n');
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_with(text: &str, mode: &UpscaleMode, opt: &LintOptions) -> Vec<Violation> {
+        lint_svg(text, &usvg::fontdb::Database::new(), mode, opt)
+    }
+
+    fn lint(text: &str, mode: &UpscaleMode) -> Vec<Rule> {
+        let opt = LintOptions {
+            include_alpha: false,
+            paint_only: false,
+            palette: None,
+        };
+        lint_with(text, mode, &opt)
+            .into_iter()
+            .map(|violation| violation.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let svg = |width: f32, body: &str| {
+            format!(
+                r##"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="5" viewBox="0 0 {0} 5">{1}</svg>"##,
+                width, body
+            )
+        };
+        let art = r##"<rect x="1" y="1" width="6" height="3" fill="#336699"/>"##;
+        let border = concat!(
+            r##"<rect width="2" height="1" fill="#ff00ff"/>"##,
+            r##"<rect width="1" height="2" fill="#ff00ff"/>"##,
+            r##"<rect x="7" y="4" width="1" height="1" fill="#ff00ff"/>"##,
+        );
+
+        assert_eq!(lint(&svg(8.0, art), &UpscaleMode::Normal), vec![]);
+        assert_eq!(
+            lint(&svg(8.5, art), &UpscaleMode::Normal),
+            vec![Rule::FractionalSize]
+        );
+        assert_eq!(
+            lint(&svg(8.0, art), &UpscaleMode::HORIZONTAL_BUTTON),
+            vec![Rule::TileCount]
+        );
+        // 6 pixels inside the borders divide into 3 tiles
+        assert_eq!(
+            lint(
+                &svg(8.0, &format!("{}{}", art, border)),
+                &UpscaleMode::HORIZONTAL_BUTTON
+            ),
+            vec![]
+        );
+        assert_eq!(
+            lint(
+                &svg(
+                    8.0,
+                    r##"<rect x="3" y="0" width="1" height="1" fill="#ff00ff"/>"##
+                ),
+                &UpscaleMode::Normal
+            ),
+            vec![Rule::MalformedBorder]
+        );
+        assert_eq!(
+            lint(
+                &svg(
+                    8.0,
+                    r##"<rect x="3" y="2" width="1" height="1" fill="#ffff00"/>"##
+                ),
+                &UpscaleMode::Normal
+            ),
+            vec![Rule::ReservedColor]
+        );
+
+        // a border from the metadata is added around the whole image
+        let metadata = |pink: &str| {
+            format!(
+                r##"<svg xmlns="http://www.w3.org/2000/svg" width="6" height="3" viewBox="0 0 6 3" data-reaper-pink="{}">{}</svg>"##,
+                pink, r##"<rect width="6" height="3" fill="#336699"/>"##
+            )
+        };
+        assert_eq!(
+            lint(&metadata("2,0,2,0"), &UpscaleMode::HORIZONTAL_BUTTON),
+            vec![]
+        );
+        assert_eq!(
+            lint(&metadata("7,0,2,0"), &UpscaleMode::Normal),
+            vec![Rule::MalformedBorder]
+        );
+        assert_eq!(
+            lint(&metadata("2,0"), &UpscaleMode::Normal),
+            vec![Rule::MalformedBorder]
+        );
+    }
+
+    #[test]
+    fn test_invalid_svg() {
+        assert_eq!(lint("<svg", &UpscaleMode::Normal), vec![Rule::InvalidSvg]);
+    }
+
+    #[test]
+    fn test_palette() {
+        let text = "accent = \"#336699\"\nhalf = \"#33669980\"\n";
+        let palette = Palette::parse(text, Path::new("palette.toml")).unwrap();
+
+        let svg = |body: &str| {
+            format!(
+                r##"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4" viewBox="0 0 4 4">{}</svg>"##,
+                body
+            )
+        };
+        let lint = |text: &str, include_alpha| {
+            let opt = LintOptions {
+                include_alpha,
+                paint_only: false,
+                palette: Some(&palette),
+            };
+            lint_with(text, &UpscaleMode::Normal, &opt)
+                .into_iter()
+                .map(|violation| (violation.rule, violation.message, violation.location))
+                .collect::<Vec<_>>()
+        };
+
+        let text = svg(concat!(
+            r##"<rect width="2" height="2" fill="#336699"/>"##,
+            "\n",
+            r##"<rect id="r" x="2" width="2" height="2" fill="#f00"/>"##
+        ));
+        assert_eq!(
+            lint(&text, false),
+            vec![(
+                Rule::UnknownColor,
+                "#ff0000 is not in the palette".into(),
+                Some(ColorLocation {
+                    line: 2,
+                    column: 47,
+                    id: Some("r".into())
+                })
+            )]
+        );
+
+        // with alpha, colors must match the palette including their alpha
+        let text = svg(concat!(
+            r##"<rect width="2" height="2" fill="#336699" fill-opacity="0.5"/>"##,
+            r##"<rect x="2" width="2" height="2" fill="#336699" fill-opacity="0.25"/>"##
+        ));
+        let rules: Vec<_> = lint(&text, true)
+            .into_iter()
+            .map(|(rule, message, _)| (rule, message))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![(Rule::UnknownColor, "#33669940 is not in the palette".into())]
+        );
+        assert_eq!(lint(&text, false), vec![]);
+    }
+
+    #[test]
+    fn test_format_results() {
+        let results = vec![
+            (
+                Path::new("a.svg"),
+                vec![
+                    violation(Rule::TileCount, "3 tiles".into()),
+                    Violation {
+                        rule: Rule::UnknownColor,
+                        message: "#ff0000 is not in the palette".into(),
+                        location: Some(ColorLocation {
+                            line: 2,
+                            column: 5,
+                            id: None,
+                        }),
+                    },
+                ],
+            ),
+            (Path::new("b.svg"), vec![]),
+        ];
+        assert_eq!(
+            format_results(&results, LintFormat::Text),
+            "a.svg: tile-count: 3 tiles\na.svg: 2:5: unknown-color: #ff0000 is not in the palette\n"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_results(&results, LintFormat::Json)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"file": "a.svg", "rule": "tile-count", "message": "3 tiles"},
+                {
+                    "file": "a.svg",
+                    "rule": "unknown-color",
+                    "message": "#ff0000 is not in the palette",
+                    "line": 2,
+                    "column": 5,
+                    "id": null,
+                },
+            ])
+        );
+    }
+}
@@ -1,307 +1,929 @@
 mod bounds;
+mod cache;
 mod cli;
+mod delta_e;
+mod error;
+mod hsl;
+mod inputs;
+mod lint;
+mod manifest;
 mod map_colors;
+mod named_colors;
+mod notation;
+mod options;
+mod paint;
+mod palette;
 mod parser;
 mod render;
+mod report;
+mod template;
+mod theme_zip;
+mod watch;
 
 use std::{
-    cell::OnceCell,
+    cmp::Reverse,
     collections::HashMap,
     fs,
     io::{self, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
 };
 
-use cli::{Options, RenderTask, StdinRenderTask};
+use cli::{
+    BatchOptions, ColorFormat, ColorMapping, FailureMode, LintFormat, Options, Output,
+    RecolorOutput, RenderTask, StdinRenderTask,
+};
+use error::Error;
 use parser::Color;
+use rayon::prelude::*;
+use resvg::tiny_skia::Pixmap;
 
 use crate::{
-    bounds::detect_reaper_bounds,
+    bounds::{has_border_pixels, metadata_bounds, parse_reaper_bounds, ReaperBounds, Rounding},
+    cache::{fonts_fingerprint, output_key, OutputCache},
     cli::TileSetting,
-    map_colors::{get_colors, map_colors},
-    render::{render, render_upscaled, UpscaleMode},
+    inputs::InputFile,
+    lint::LintOptions,
+    manifest::Manifest,
+    map_colors::{get_color_locations, map_colors, ColorLocation},
+    notation::to_hex,
+    options::RenderOptions,
+    palette::Palette,
+    render::{render, render_upscaled, Borders, UpscaleMode},
+    report::{cluster_reports, format_report, ColorReport, ReportOptions},
+    template::check_duplicate_outputs,
+    theme_zip::ThemeArchive,
+    watch::Watcher,
 };
 
-fn cli_colors(paths: Vec<PathBuf>, print_count: bool, include_alpha: bool) {
-    let counts = paths
-        .iter()
-        .map(|path| {
-            // read the input SVG into text
-            let path = path.as_path();
-            let text = fs::read_to_string(&path)
-                .expect(format!("failed to read svg: {}", path.display()).as_str());
-
-            // parse colors in the SVG and map them
-            let original_colors = get_colors(&text, include_alpha)
-                .expect(format!("failed to parse svg: {}", path.display()).as_str());
+fn cli_colors(
+    paths: Vec<PathBuf>,
+    format: ColorFormat,
+    filter: Option<Color>,
+    cluster: Option<f32>,
+    include_alpha: bool,
+    paint_only: bool,
+    opt: ReportOptions,
+) -> Result<(), Error> {
+    // locations of each color, grouped by file
+    let mut locations = HashMap::<Color, Vec<(&Path, Vec<ColorLocation>)>>::new();
+    for path in &paths {
+        // read the input SVG into text
+        let path = path.as_path();
+        let text = fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+
+        // parse colors in the SVG
+        let mut colors = HashMap::<Color, Vec<ColorLocation>>::new();
+        let color_locations = get_color_locations(&text, include_alpha, paint_only)
+            .map_err(|err| Error::ParseColors(format!("{}:{}", path.display(), err)))?;
+        for (color, location) in color_locations {
+            if filter.as_ref().is_none_or(|filter| *filter == color) {
+                colors.entry(color).or_default().push(location);
+            }
+        }
+        for (color, color_locations) in colors {
+            locations
+                .entry(color)
+                .or_default()
+                .push((path, color_locations));
+        }
+    }
 
-            original_colors
+    // most used colors first, then by color for a stable output
+    let mut reports: Vec<_> = locations
+        .into_iter()
+        .map(|(color, files)| ColorReport {
+            color,
+            files,
+            similar: Vec::new(),
         })
-        .fold(HashMap::<Color, u32>::new(), |mut acc, colors| {
-            for color in colors {
-                match acc.get(&color) {
-                    Some(count) => acc.insert(color, count + 1),
-                    None => acc.insert(color, 1),
-                };
-            }
-            acc
-        });
+        .collect();
+    reports.sort_by_key(|report| (Reverse(report.files.len()), to_hex(&report.color)));
+    if let Some(max_delta_e) = cluster {
+        reports = cluster_reports(reports, max_delta_e);
+    }
 
-    let mut counts: Vec<_> = counts.into_iter().collect();
-    counts.sort_by_key(|(_, count)| *count);
+    print!("{}", format_report(&reports, format, &opt));
+    Ok(())
+}
 
-    for (color, count) in counts.iter().rev() {
-        if print_count {
-            println!("{} {}", count, color.to_string());
-        } else {
-            println!("{}", color.to_string());
+fn load_fontdb(fonts_dir: Option<&Path>) -> resvg::usvg::fontdb::Database {
+    let mut db = resvg::usvg::fontdb::Database::new();
+    if let Some(path) = fonts_dir {
+        db.load_fonts_dir(path);
+    }
+    db
+}
+
+/// The fonts of a batch, along with a fingerprint of their files for the output cache
+struct Fonts {
+    db: resvg::usvg::fontdb::Database,
+    fingerprint: u64,
+}
+
+impl Fonts {
+    fn load(fonts_dir: Option<&Path>) -> Self {
+        Self {
+            db: load_fontdb(fonts_dir),
+            fingerprint: fonts_fingerprint(fonts_dir),
         }
     }
 }
 
-pub(crate) struct RenderOptions {
-    pub(crate) all_input_colors: bool,
-    pub(crate) all_svg_colors: bool,
-    pub(crate) include_alpha: bool,
+fn upscale_mode(tile_setting: Option<&TileSetting>) -> UpscaleMode {
+    match tile_setting {
+        Some(ts) => match ts {
+            TileSetting::HorizontalButton => UpscaleMode::HORIZONTAL_BUTTON,
+            TileSetting::VerticalButton => UpscaleMode::VERTICAL_BUTTON,
+            TileSetting::Grid { tx, ty } => UpscaleMode::Grid {
+                x: (*tx).into(),
+                y: (*ty).into(),
+            },
+            TileSetting::HorizontalTiles { tx } => UpscaleMode::HorizontalTiles((*tx).into()),
+            TileSetting::VerticalTiles { ty } => UpscaleMode::VerticalTiles((*ty).into()),
+        },
+        None => UpscaleMode::Normal,
+    }
+}
+
+/// Save an output image, either to its path or into the theme archive
+fn save_output(
+    pixmap: &Pixmap,
+    output: &Output,
+    archive: Option<&ThemeArchive>,
+) -> Result<(), Error> {
+    match archive {
+        Some(archive) => archive.add_image(pixmap, output),
+        None => pixmap
+            .save_png(&output.output)
+            .map_err(|err| Error::Write(output.output.clone(), err.to_string())),
+    }
+}
+
+/// Map the colors of an SVG, reporting colors that were mapped as near matches
+fn map_task_colors(
+    text: &str,
+    source: &Path,
+    color_mappings: &[ColorMapping],
+    opt: &RenderOptions,
+) -> Result<String, Error> {
+    let mut color_map: HashMap<Color, Color> = HashMap::new();
+
+    for cm in color_mappings {
+        color_map.insert(cm.old.clone(), cm.new.clone());
+    }
+
+    let mapped = map_colors(text, &color_map, opt).map_err(Error::MapColors)?;
+    for near_match in mapped.near_matches {
+        eprintln!(
+            "{}: mapped {} to {} as a near match of {}",
+            source.display(),
+            near_match.color,
+            near_match.new,
+            near_match.source
+        );
+    }
+    Ok(mapped.text)
+}
+
+fn parse_tree(
+    text: &str,
+    fontdb: &resvg::usvg::fontdb::Database,
+) -> Result<resvg::usvg::Tree, Error> {
+    resvg::usvg::Tree::from_str(text, &resvg::usvg::Options::default(), fontdb).map_err(|err| {
+        // keep the mapped SVG around for debugging
+        let _ = fs::write("error.svg", text);
+        Error::ParseSvg(err)
+    })
 }
 
-fn cli_render(tasks: Vec<RenderTask>, fonts_dir: Option<PathBuf>, opt: &RenderOptions) {
-    let fontdb = {
-        let mut db = resvg::usvg::fontdb::Database::new();
-        if let Some(path) = fonts_dir {
-            db.load_fonts_dir(path);
+/// Render a parsed SVG to all of its outputs in parallel. `inserted` is a border to add
+/// around the SVG, otherwise borders are detected in the SVG.
+fn render_outputs(
+    tree: &resvg::usvg::Tree,
+    source: &Path,
+    upscale_mode: &UpscaleMode,
+    rounding: Rounding,
+    inserted: Option<&ReaperBounds>,
+    outputs: &[Output],
+    archive: Option<&ThemeArchive>,
+) -> Result<(), Error> {
+    if let Some(bounds) = inserted {
+        // every scale needs the border added, including the normal scale
+        return outputs.par_iter().try_for_each(|output| {
+            let borders = Borders::Inserted(bounds);
+            let pixmap = render_upscaled(tree, output.scale, upscale_mode, borders, rounding)?;
+            save_output(&pixmap, output, archive)
+        });
+    }
+
+    let scale_1_pixmap = render(tree)?;
+    let detected_bounds = OnceLock::new();
+
+    outputs.par_iter().try_for_each(|output| {
+        if output.scale == 1.0 {
+            // no scaling, just save the image
+            return save_output(&scale_1_pixmap, output, archive);
         }
-        db
+
+        let detected_bounds = detected_bounds.get_or_init(|| {
+            parse_reaper_bounds(&scale_1_pixmap)
+                .inspect_err(|err| {
+                    // only warn about images that look like they were meant to have borders
+                    if has_border_pixels(&scale_1_pixmap) {
+                        eprintln!(
+                            "{}: warning: upscaling without borders, they are malformed: {}",
+                            source.display(),
+                            err
+                        );
+                    }
+                })
+                .ok()
+        });
+        let borders = match detected_bounds {
+            Some(bounds) => Borders::Drawn(bounds),
+            None => Borders::None,
+        };
+
+        // there are bounds, preprocess then rescale
+        let pixmap = render_upscaled(tree, output.scale, upscale_mode, borders, rounding)?;
+
+        save_output(&pixmap, output, archive)
+    })
+}
+
+/// Number of outputs handled by a task
+struct TaskStats {
+    rendered: usize,
+    skipped: usize,
+}
+
+/// Render a task, skipping outputs that are up to date in the cache
+fn render_task(
+    task: &RenderTask,
+    fonts: &Fonts,
+    opt: &RenderOptions,
+    cache: Option<&OutputCache>,
+    archive: Option<&ThemeArchive>,
+) -> Result<TaskStats, Error> {
+    // read the input SVG into text
+    let path = task.input.as_path();
+    let text = fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+
+    // parse colors in the SVG and map them
+    let text = map_task_colors(&text, path, &task.color_mappings, opt)?;
+
+    let upscale_mode = upscale_mode(task.tile_setting.as_ref());
+
+    // a border from the manifest takes priority over the SVG's metadata
+    let inserted = match &task.borders {
+        Some(bounds) => Some(bounds.clone()),
+        None => metadata_bounds(&text)?,
     };
 
-    for task in tasks.iter() {
-        // read the input SVG into text
-        let path = task.input.as_path();
-        let mut text = fs::read_to_string(&path)
-            .expect(format!("failed to read svg: {}", path.display()).as_str());
+    // find the outputs that need to be rendered
+    let outputs: Vec<(Output, u64)> = task
+        .outputs
+        .iter()
+        .map(|output| {
+            (
+                output.clone(),
+                output_key(
+                    &text,
+                    &upscale_mode,
+                    output.scale,
+                    opt.bounds_rounding,
+                    inserted.as_ref(),
+                    fonts.fingerprint,
+                ),
+            )
+        })
+        .filter(|(output, key)| !cache.is_some_and(|cache| cache.is_fresh(&output.output, *key)))
+        .collect();
+    let stats = TaskStats {
+        rendered: outputs.len(),
+        skipped: task.outputs.len() - outputs.len(),
+    };
+    if outputs.is_empty() {
+        return Ok(stats);
+    }
 
-        // parse colors in the SVG and map them
-        {
-            let mut color_map: HashMap<Color, Color> = HashMap::new();
+    let tree = parse_tree(&text, &fonts.db)?;
+
+    let (outputs, keys): (Vec<_>, Vec<_>) = outputs.into_iter().unzip();
+    render_outputs(
+        &tree,
+        path,
+        &upscale_mode,
+        opt.bounds_rounding,
+        inserted.as_ref(),
+        &outputs,
+        archive,
+    )?;
+
+    if let Some(cache) = cache {
+        for (output, key) in outputs.iter().zip(keys) {
+            cache.update(&output.output, key);
+        }
+    }
 
-            for cm in &task.color_mappings {
-                color_map.insert(cm.old.clone(), cm.new.clone());
-            }
+    Ok(stats)
+}
 
-            text = match map_colors(&text, &color_map, opt) {
-                Ok(x) => x,
-                Err(err) => {
-                    println!("failed to map colors: {}: {}", path.display(), err);
-                    continue;
+/// Render a batch of tasks on the thread pool, then print a summary of the results.
+/// Returns the number of tasks that failed.
+fn render_batch(
+    tasks: &[&RenderTask],
+    fonts: &Fonts,
+    opt: &RenderOptions,
+    batch: &BatchOptions,
+    pool: &rayon::ThreadPool,
+    use_cache: bool,
+    archive: Option<&ThemeArchive>,
+) -> usize {
+    let cache = use_cache.then(OutputCache::default);
+    let stop = AtomicBool::new(false);
+
+    // `None` means the task was never started, because an earlier task failed
+    let results: Vec<Option<Result<TaskStats, Error>>> = pool.install(|| {
+        tasks
+            .par_iter()
+            .map(|task| {
+                if stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                let result = render_task(task, fonts, opt, cache.as_ref(), archive);
+                if result.is_err() && matches!(batch.failure_mode, FailureMode::FailFast) {
+                    stop.store(true, Ordering::Relaxed);
                 }
+                Some(result)
+            })
+            .collect()
+    });
+
+    if let Some(cache) = &cache {
+        if let Err(err) = cache.save() {
+            eprintln!("failed to save cache: {}", err);
+        }
+    }
+
+    let mut rendered = 0;
+    let mut skipped = 0;
+    let mut not_started = 0;
+    let mut failures = Vec::new();
+    for (task, result) in tasks.iter().zip(results) {
+        match result {
+            Some(Ok(stats)) => {
+                rendered += stats.rendered;
+                skipped += stats.skipped;
             }
+            Some(Err(err)) => failures.push((task, err)),
+            None => not_started += 1,
         }
+    }
 
-        let tree = resvg::usvg::Tree::from_str(&text, &resvg::usvg::Options::default(), &fontdb)
-            .or_else(|x| {
-                fs::write("error.svg", text).unwrap();
-                Err(x)
-            })
-            .expect("failed to parse svg");
+    if skipped > 0 {
+        println!(
+            "rendered {} outputs, skipped {} unchanged outputs",
+            rendered, skipped
+        );
+    }
+    if !failures.is_empty() {
+        eprintln!("{} of {} tasks failed:", failures.len(), tasks.len());
+        for (task, err) in &failures {
+            eprintln!("  {}: {}", task.input.display(), err);
+        }
+    }
+    if not_started > 0 {
+        eprintln!(
+            "stopped after the first failure, {} tasks were not rendered",
+            not_started
+        );
+    }
 
-        let scale_1_pixmap = render(&tree).unwrap();
-        let detected_bounds = OnceCell::new();
+    failures.len()
+}
 
-        for output in &task.outputs {
-            if output.scale == 1.0 {
-                // no scaling, just save the image
-                scale_1_pixmap.save_png(output.output.as_path()).unwrap();
-                continue;
+/// Render all tasks. In watch mode, keep re-rendering the tasks whose inputs have changed,
+/// until any of the `reload_paths` change.
+fn run_tasks(
+    tasks: &[RenderTask],
+    fonts_dir: Option<&Path>,
+    opt: &RenderOptions,
+    batch: &BatchOptions,
+    reload_paths: &[PathBuf],
+) -> Result<(), Error> {
+    let mut fonts = Fonts::load(fonts_dir);
+
+    // the pool uses one thread per CPU when no job count is given
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(batch.jobs.unwrap_or(0))
+        .build()
+        .expect("failed to create thread pool");
+
+    let archive = batch.zip.as_ref().map(ThemeArchive::new);
+    match &archive {
+        Some(archive) => archive.check_entries(tasks)?,
+        None => check_duplicate_outputs(tasks)?,
+    }
+    // the archive is rewritten from memory, so every image must be rendered
+    let use_cache = !batch.force && archive.is_none();
+
+    let render_and_package = |tasks: &[&RenderTask], fonts: &Fonts, use_cache: bool| {
+        let failed = render_batch(tasks, fonts, opt, batch, &pool, use_cache, archive.as_ref());
+        if failed > 0 {
+            return Err(Error::TasksFailed(failed, tasks.len()));
+        }
+        if let Some(archive) = &archive {
+            archive.write()?;
+        }
+        Ok(())
+    };
+
+    let all_tasks: Vec<_> = tasks.iter().collect();
+    let result = render_and_package(&all_tasks, &fonts, use_cache);
+
+    if !batch.watch {
+        return result;
+    }
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+    }
+
+    let mut watcher = Watcher::new(
+        tasks
+            .iter()
+            .map(|task| task.input.clone())
+            .chain(fonts_dir.map(Path::to_path_buf))
+            .chain(reload_paths.iter().cloned()),
+    );
+    println!("watching {} files for changes...", watcher.file_count());
+
+    loop {
+        let changed = watcher.wait_for_changes();
+        if reload_paths.iter().any(|path| changed.contains(path)) {
+            return Ok(());
+        }
+
+        // fonts affect every task, their fingerprint in the cache key makes every output stale
+        let fonts_changed =
+            fonts_dir.is_some_and(|dir| changed.iter().any(|path| path.starts_with(dir)));
+        let result = if fonts_changed {
+            println!("fonts changed, re-rendering all files");
+            fonts = Fonts::load(fonts_dir);
+            render_and_package(&all_tasks, &fonts, use_cache)
+        } else {
+            let changed_tasks: Vec<_> = tasks
+                .iter()
+                .filter(|task| changed.contains(&task.input))
+                .collect();
+            for task in &changed_tasks {
+                println!("changed: {}", task.input.display());
             }
+            render_and_package(&changed_tasks, &fonts, use_cache)
+        };
+
+        if let Err(err) = result {
+            eprintln!("error: {}", err);
+        }
+    }
+}
+
+/// Outputs may be spread over many directories, create them up front
+fn create_output_dirs(tasks: &[RenderTask], batch: &BatchOptions) -> Result<(), Error> {
+    if batch.zip.is_some() {
+        return Ok(());
+    }
+    for output in tasks.iter().flat_map(|task| &task.outputs) {
+        if let Some(parent) = output.output.parent() {
+            fs::create_dir_all(parent).map_err(|err| Error::CreateDir(parent.to_owned(), err))?;
+        }
+    }
+    Ok(())
+}
+
+fn load_palette(path: Option<&Path>) -> Result<Palette, Error> {
+    Ok(match path {
+        Some(path) => Palette::load(path)?,
+        None => Palette::default(),
+    })
+}
+
+/// Prepend the mappings from a task's mapping files to its `-m` mappings
+fn load_mapping_files(
+    mapping_files: &[PathBuf],
+    color_mappings: &[ColorMapping],
+    palette: &Palette,
+) -> Result<Vec<ColorMapping>, Error> {
+    let mut result = Vec::new();
+    for path in mapping_files {
+        result.extend(palette.load_mappings(path)?);
+    }
+    result.extend(color_mappings.iter().cloned());
+    Ok(result)
+}
+
+/// Expand directories and glob patterns into one task per SVG, output templates into
+/// regular outputs, and mapping files into regular mappings
+fn resolve_tasks(tasks: &[RenderTask], palette: Option<&Path>) -> Result<Vec<RenderTask>, Error> {
+    let palette = load_palette(palette)?;
 
-            if output.scale < 1.0 {
-                todo!("throw error, not supported");
+    let mut resolved = Vec::new();
+    for task in tasks {
+        let color_mappings =
+            load_mapping_files(&task.mapping_files, &task.color_mappings, &palette)?;
+
+        for input in inputs::expand_input(&task.input)? {
+            let mut outputs = task.outputs.clone();
+            if let Some(template) = &task.output_template {
+                outputs.extend(template::expand_outputs(template, &input)?);
             }
+            resolved.push(RenderTask {
+                input: input.path,
+                color_mappings: color_mappings.clone(),
+                mapping_files: Vec::new(),
+                tile_setting: task.tile_setting.clone(),
+                outputs,
+                output_template: None,
+                borders: task.borders.clone(),
+            });
+        }
+    }
+    Ok(resolved)
+}
 
-            let output_path = output.output.as_path();
-
-            let detected_bounds =
-                detected_bounds.get_or_init(|| detect_reaper_bounds(&scale_1_pixmap));
-            let (yellow_bounds, pink_bounds) = detected_bounds
-                .as_ref()
-                .map(|(a, b)| (Some(a), Some(b)))
-                .unwrap_or((None, None));
-
-            // there are bounds, preprocess then upscale
-            let upscale_mode = match &task.tile_setting {
-                Some(ts) => match &ts {
-                    TileSetting::HorizontalButton => UpscaleMode::HORIZONTAL_BUTTON,
-                    TileSetting::VerticalButton => UpscaleMode::VERTICAL_BUTTON,
-                    TileSetting::Grid { tx, ty } => UpscaleMode::Grid {
-                        x: (*tx).into(),
-                        y: (*ty).into(),
-                    },
-                    TileSetting::HorizontalTiles { tx } => {
-                        UpscaleMode::HorizontalTiles((*tx).into())
-                    }
-                    TileSetting::VerticalTiles { ty } => UpscaleMode::VerticalTiles((*ty).into()),
-                },
-                None => UpscaleMode::Normal,
-            };
+fn cli_render(
+    tasks: Vec<RenderTask>,
+    fonts_dir: Option<PathBuf>,
+    palette: Option<PathBuf>,
+    opt: &RenderOptions,
+    batch: &BatchOptions,
+) -> Result<(), Error> {
+    // the palette and mapping files are re-read when they change
+    let reload_paths: Vec<PathBuf> = palette
+        .iter()
+        .chain(tasks.iter().flat_map(|task| &task.mapping_files))
+        .cloned()
+        .collect();
+
+    loop {
+        let resolved = match resolve_tasks(&tasks, palette.as_deref()) {
+            Ok(x) => x,
+            Err(err) if batch.watch && !reload_paths.is_empty() => {
+                eprintln!("error: {}", err);
+
+                // wait for the palette or mapping files to be fixed
+                Watcher::new(reload_paths.iter().cloned()).wait_for_changes();
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
 
-            let pixmap = render_upscaled(
-                &tree,
-                output.scale,
-                &upscale_mode,
-                pink_bounds,
-                yellow_bounds,
-            )
-            .unwrap();
+        create_output_dirs(&resolved, batch)?;
+        run_tasks(&resolved, fonts_dir.as_deref(), opt, batch, &reload_paths)?;
 
-            pixmap.save_png(&output_path).unwrap();
+        if !batch.watch {
+            return Ok(());
         }
+        println!("palette or mappings changed, reloading");
     }
 }
 
 fn cli_stdin_render(
-    mut text: String,
+    text: String,
     task: StdinRenderTask,
     fonts_dir: Option<PathBuf>,
+    palette: Option<PathBuf>,
     opt: &RenderOptions,
-) {
-    let fontdb = {
-        let mut db = resvg::usvg::fontdb::Database::new();
-        if let Some(path) = fonts_dir {
-            db.load_fonts_dir(path);
-        }
-        db
+) -> Result<(), Error> {
+    let fontdb = load_fontdb(fonts_dir.as_deref());
+    let palette = load_palette(palette.as_deref())?;
+    let color_mappings = load_mapping_files(&task.mapping_files, &task.color_mappings, &palette)?;
+
+    // parse colors in the SVG and map them
+    let source = Path::new("<stdin>");
+    let text = map_task_colors(&text, source, &color_mappings, opt)?;
+
+    let tree = parse_tree(&text, &fontdb)?;
+
+    let upscale_mode = upscale_mode(task.tile_setting.as_ref());
+    let inserted = metadata_bounds(&text)?;
+    render_outputs(
+        &tree,
+        source,
+        &upscale_mode,
+        opt.bounds_rounding,
+        inserted.as_ref(),
+        &task.outputs,
+        None,
+    )
+}
+
+fn cli_lint(
+    tasks: &[(PathBuf, UpscaleMode)],
+    fonts_dir: Option<PathBuf>,
+    palette: Option<&Path>,
+    format: LintFormat,
+    opt: &LintOptions,
+) -> Result<(), Error> {
+    let fontdb = load_fontdb(fonts_dir.as_deref());
+    let palette = palette.map(Palette::load).transpose()?;
+    let opt = LintOptions {
+        palette: palette.as_ref(),
+        ..*opt
     };
 
-    {
-        // parse colors in the SVG and map them
-        {
-            let mut color_map: HashMap<Color, Color> = HashMap::new();
+    let mut results = Vec::new();
+    for (path, upscale_mode) in tasks {
+        // read the input SVG into text
+        let text = fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
+        results.push((path, lint::lint_svg(&text, &fontdb, upscale_mode, &opt)));
+    }
+    print!("{}", lint::format_results(&results, format));
 
-            for cm in &task.color_mappings {
-                color_map.insert(cm.old.clone(), cm.new.clone());
-            }
+    let count: usize = results.iter().map(|(_, violations)| violations.len()).sum();
+    let files = results
+        .iter()
+        .filter(|(_, violations)| !violations.is_empty())
+        .count();
+    match count {
+        0 => Ok(()),
+        _ => Err(Error::LintFailed(count, files)),
+    }
+}
 
-            text = match map_colors(&text, &color_map, opt) {
-                Ok(x) => x,
-                Err(err) => {
-                    println!("failed to map colors: {}", err);
-                    return;
-                }
+/// Path with a suffix added to its file name, e.g. `a.svg.bak`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+fn cli_recolor(
+    paths: &[PathBuf],
+    color_mappings: &[ColorMapping],
+    mapping_files: &[PathBuf],
+    palette: Option<&Path>,
+    output: &RecolorOutput,
+    opt: &RenderOptions,
+) -> Result<(), Error> {
+    let palette = load_palette(palette)?;
+    let color_mappings = load_mapping_files(mapping_files, color_mappings, &palette)?;
+
+    let mut files = Vec::new();
+    for path in paths {
+        for file in inputs::expand_input(path)? {
+            // an SVG may be matched by several inputs, only recolor it once
+            if !files
+                .iter()
+                .any(|other: &InputFile| other.path == file.path)
+            {
+                files.push(file);
             }
         }
+    }
 
-        let tree = resvg::usvg::Tree::from_str(&text, &resvg::usvg::Options::default(), &fontdb)
-            .or_else(|x| {
-                fs::write("error.svg", text).unwrap();
-                Err(x)
-            })
-            .expect("failed to parse svg");
+    let mut changed = 0;
+    for file in &files {
+        // read the input SVG into text
+        let path = file.path.as_path();
+        let text = fs::read_to_string(path).map_err(|err| Error::Read(path.to_owned(), err))?;
 
-        let scale_1_pixmap = render(&tree).unwrap();
-        let detected_bounds = OnceCell::new();
+        // parse colors in the SVG and map them
+        let mapped = map_task_colors(&text, path, &color_mappings, opt)?;
 
-        for output in &task.outputs {
-            if output.scale == 1.0 {
-                // no scaling, just save the image
-                scale_1_pixmap.save_png(output.output.as_path()).unwrap();
-                continue;
+        let output_path = match output {
+            RecolorOutput::Dir { dir } => {
+                let dir = dir.join(&file.rel_dir);
+                fs::create_dir_all(&dir).map_err(|err| Error::CreateDir(dir.clone(), err))?;
+                dir.join(path.file_name().unwrap_or_default())
             }
-
-            if output.scale < 1.0 {
-                todo!("throw error, not supported");
+            RecolorOutput::InPlace { backup, .. } => {
+                // leave unchanged SVGs alone, so that they don't get backups
+                if mapped == text {
+                    continue;
+                }
+                if let Some(suffix) = backup {
+                    let backup_path = with_suffix(path, suffix);
+                    fs::copy(path, &backup_path)
+                        .map_err(|err| Error::Write(backup_path, err.to_string()))?;
+                }
+                path.to_owned()
             }
+        };
+        fs::write(&output_path, &mapped)
+            .map_err(|err| Error::Write(output_path.clone(), err.to_string()))?;
+        if mapped != text {
+            changed += 1;
+        }
+    }
 
-            let output_path = output.output.as_path();
-
-            let detected_bounds =
-                detected_bounds.get_or_init(|| detect_reaper_bounds(&scale_1_pixmap));
-            let (yellow_bounds, pink_bounds) = detected_bounds
-                .as_ref()
-                .map(|(a, b)| (Some(a), Some(b)))
-                .unwrap_or((None, None));
-
-            // there are bounds, preprocess then upscale
-            let upscale_mode = match &task.tile_setting {
-                Some(ts) => match &ts {
-                    TileSetting::HorizontalButton => UpscaleMode::HORIZONTAL_BUTTON,
-                    TileSetting::VerticalButton => UpscaleMode::VERTICAL_BUTTON,
-                    TileSetting::Grid { tx, ty } => UpscaleMode::Grid {
-                        x: (*tx).into(),
-                        y: (*ty).into(),
-                    },
-                    TileSetting::HorizontalTiles { tx } => {
-                        UpscaleMode::HorizontalTiles((*tx).into())
-                    }
-                    TileSetting::VerticalTiles { ty } => UpscaleMode::VerticalTiles((*ty).into()),
-                },
-                None => UpscaleMode::Normal,
-            };
-
-            let pixmap = render_upscaled(
-                &tree,
-                output.scale,
-                &upscale_mode,
-                pink_bounds,
-                yellow_bounds,
-            )
-            .unwrap();
+    println!("recolored {} of {} SVGs", changed, files.len());
+    Ok(())
+}
 
-            pixmap.save_png(&output_path).unwrap();
+fn cli_build(
+    manifest_path: &Path,
+    fonts_dir: Option<PathBuf>,
+    batch: &BatchOptions,
+) -> Result<(), Error> {
+    loop {
+        let manifest = match Manifest::load(manifest_path) {
+            Ok(x) => x,
+            Err(err) if batch.watch => {
+                eprintln!("{}: {}", manifest_path.display(), err);
+
+                // wait for the manifest to be fixed
+                Watcher::new([manifest_path.to_path_buf()]).wait_for_changes();
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        create_output_dirs(&manifest.tasks, batch)?;
+
+        let mut reload_paths = vec![manifest_path.to_path_buf()];
+        reload_paths.extend(manifest.sources);
+        run_tasks(
+            &manifest.tasks,
+            fonts_dir.as_deref().or(manifest.fonts.as_deref()),
+            &manifest.options,
+            batch,
+            &reload_paths,
+        )?;
+
+        if !batch.watch {
+            return Ok(());
         }
+        println!("manifest changed, reloading");
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let opt = cli::options().run();
 
-    match opt {
+    let result = match opt {
         Options::Render {
             fonts,
             tasks,
             all_input_colors,
             all_svg_colors,
             include_alpha,
+            paint_only,
+            palette,
+            transform,
+            tolerance,
+            bounds_rounding,
+            batch,
         } => cli_render(
             tasks,
             fonts,
+            palette,
             &RenderOptions {
                 all_input_colors,
                 all_svg_colors,
                 include_alpha,
+                paint_only,
+                transform,
+                tolerance,
+                bounds_rounding,
             },
+            &batch,
         ),
         Options::RenderStdin {
             fonts,
             all_input_colors,
             all_svg_colors,
             include_alpha,
+            paint_only,
+            palette,
+            transform,
+            tolerance,
+            bounds_rounding,
             task,
         } => {
-            let input: String = {
-                let stdin = io::stdin();
-                let mut buf = Vec::new();
-                stdin
-                    .lock()
-                    .read_to_end(&mut buf)
-                    .expect("failed to read stdin");
-                String::from_utf8(buf).expect("input is invalid utf8")
-            };
-
-            cli_stdin_render(
-                input,
-                task,
-                fonts,
-                &RenderOptions {
-                    all_input_colors,
-                    all_svg_colors,
-                    include_alpha,
-                },
-            );
+            let mut input = String::new();
+            match io::stdin().lock().read_to_string(&mut input) {
+                Ok(_) => cli_stdin_render(
+                    input,
+                    task,
+                    fonts,
+                    palette,
+                    &RenderOptions {
+                        all_input_colors,
+                        all_svg_colors,
+                        include_alpha,
+                        paint_only,
+                        transform,
+                        tolerance,
+                        bounds_rounding,
+                    },
+                ),
+                Err(err) => Err(Error::ReadStdin(err)),
+            }
         }
         Options::Colors {
             paths,
             count,
+            locations,
+            filter,
+            cluster,
+            format,
             include_alpha,
-        } => cli_colors(paths, count, include_alpha),
+            paint_only,
+        } => {
+            let opt = ReportOptions {
+                count,
+                locations: locations || filter.is_some(),
+                alpha: include_alpha,
+                cluster: cluster.is_some(),
+            };
+            cli_colors(
+                paths,
+                format,
+                filter,
+                cluster,
+                include_alpha,
+                paint_only,
+                opt,
+            )
+        }
+        Options::Build {
+            fonts,
+            batch,
+            manifest,
+        } => cli_build(&manifest, fonts, &batch),
+        Options::Lint {
+            fonts,
+            include_alpha,
+            paint_only,
+            palette,
+            manifest,
+            tile_setting,
+            format,
+            inputs,
+        } => (|| {
+            // SVGs with the tile settings to check them with
+            let mut tasks = Vec::new();
+            if let Some(manifest) = manifest {
+                for task in Manifest::load(&manifest)?.tasks {
+                    let mode = upscale_mode(task.tile_setting.as_ref());
+                    tasks.push((task.input, mode));
+                }
+            }
+            for input in &inputs {
+                for file in inputs::expand_input(input)? {
+                    tasks.push((file.path, upscale_mode(tile_setting.as_ref())));
+                }
+            }
+
+            let opt = LintOptions {
+                include_alpha,
+                paint_only,
+                palette: None,
+            };
+            cli_lint(&tasks, fonts, palette.as_deref(), format, &opt)
+        })(),
+        Options::Recolor {
+            all_input_colors,
+            all_svg_colors,
+            include_alpha,
+            paint_only,
+            palette,
+            transform,
+            tolerance,
+            color_mappings,
+            mapping_files,
+            output,
+            inputs,
+        } => cli_recolor(
+            &inputs,
+            &color_mappings,
+            &mapping_files,
+            palette.as_deref(),
+            &output,
+            &RenderOptions {
+                all_input_colors,
+                all_svg_colors,
+                include_alpha,
+                paint_only,
+                transform,
+                tolerance,
+                bounds_rounding: Rounding::default(),
+            },
+        ),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
     }
 }
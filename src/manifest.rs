@@ -0,0 +1,547 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    bounds::{Bounds, ReaperBounds, Rounding},
+    cli::{ColorMapping, HslTransform, Output, RenderTask, TileSetting, Tolerance},
+    inputs::{expand_input, InputError},
+    options::RenderOptions,
+    palette::{Palette, PaletteError},
+    template::{self, TemplateError, TemplateVars},
+};
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("failed to read manifest {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("failed to parse manifest: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("failed to parse manifest: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error(transparent)]
+    Palette(#[from] PaletteError),
+    #[error("no outputs specified for {0:?}")]
+    NoOutputs(String),
+    #[error(transparent)]
+    Template(#[from] TemplateError),
+}
+
+/// A tile setting, either as a shorthand name or as a table of tile counts
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "TileForm")]
+enum TileEntry {
+    Named(TileName),
+    Grid { tx: NonZeroU32, ty: NonZeroU32 },
+    HorizontalTiles { tx: NonZeroU32 },
+    VerticalTiles { ty: NonZeroU32 },
+}
+
+/// How a tile setting is written. The table is parsed as a whole rather than as one
+/// untagged variant per combination of keys, so that a typo like `tz` is an error instead
+/// of matching a table with fewer keys.
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "expected none, hb, vb, or a table with tx, ty or both and no other keys"
+)]
+enum TileForm {
+    Named(TileName),
+    Counts(TileCounts),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TileCounts {
+    tx: Option<NonZeroU32>,
+    ty: Option<NonZeroU32>,
+}
+
+impl TryFrom<TileForm> for TileEntry {
+    type Error = &'static str;
+
+    fn try_from(form: TileForm) -> Result<Self, Self::Error> {
+        match form {
+            TileForm::Named(name) => Ok(TileEntry::Named(name)),
+            TileForm::Counts(TileCounts { tx, ty }) => match (tx, ty) {
+                (Some(tx), Some(ty)) => Ok(TileEntry::Grid { tx, ty }),
+                (Some(tx), None) => Ok(TileEntry::HorizontalTiles { tx }),
+                (None, Some(ty)) => Ok(TileEntry::VerticalTiles { ty }),
+                (None, None) => Err("tile table needs tx, ty or both"),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+enum TileName {
+    /// Explicitly disable tiling, e.g. to override the default tile setting
+    None,
+    Hb,
+    Vb,
+}
+
+impl TileEntry {
+    fn to_tile_setting(&self) -> Option<TileSetting> {
+        match self {
+            TileEntry::Named(TileName::None) => None,
+            TileEntry::Named(TileName::Hb) => Some(TileSetting::HorizontalButton),
+            TileEntry::Named(TileName::Vb) => Some(TileSetting::VerticalButton),
+            TileEntry::Grid { tx, ty } => Some(TileSetting::Grid { tx: *tx, ty: *ty }),
+            TileEntry::HorizontalTiles { tx } => Some(TileSetting::HorizontalTiles { tx: *tx }),
+            TileEntry::VerticalTiles { ty } => Some(TileSetting::VerticalTiles { ty: *ty }),
+        }
+    }
+}
+
+/// Color matching tolerance, either a per-channel difference or a CIEDE2000 difference,
+/// e.g. `tolerance = 2` or `tolerance = { delta_e = 1.5 }`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ToleranceEntry {
+    Channel(u8),
+    DeltaE { delta_e: f32 },
+}
+
+impl ToleranceEntry {
+    fn to_tolerance(&self) -> Tolerance {
+        match *self {
+            ToleranceEntry::Channel(max_difference) => Tolerance::Channel { max_difference },
+            ToleranceEntry::DeltaE { delta_e } => Tolerance::DeltaE {
+                max_delta_e: delta_e,
+            },
+        }
+    }
+}
+
+/// A border to add around the SVGs, instead of drawing it in them. Bounds are written as
+/// `[l, t, r, b]`, e.g. `borders = { pink = [4, 0, 4, 0] }`. `borders = "none"` switches
+/// off the default border, so the SVGs' own borders are used.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(
+    untagged,
+    expecting = "expected none, or a table with yellow, pink or both and no other keys"
+)]
+enum BordersEntry {
+    None(NoBorders),
+    Bounds(BorderBounds),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+enum NoBorders {
+    None,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct BorderBounds {
+    yellow: Option<[u32; 4]>,
+    pink: Option<[u32; 4]>,
+}
+
+impl BordersEntry {
+    fn to_reaper_bounds(&self) -> Option<ReaperBounds> {
+        let BordersEntry::Bounds(bounds) = self else {
+            return None;
+        };
+        let parse = |values: Option<[u32; 4]>| {
+            values.map_or_else(Bounds::default, |[l, t, r, b]| Bounds { l, t, r, b })
+        };
+        Some(ReaperBounds::new(parse(bounds.yellow), parse(bounds.pink)))
+    }
+}
+
+fn default_saturation() -> f32 {
+    1.0
+}
+
+/// Global HSL transform, see `HslTransform`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TransformEntry {
+    #[serde(default)]
+    hue_rotate: f32,
+    #[serde(default = "default_saturation")]
+    saturation: f32,
+    #[serde(default)]
+    lightness: f32,
+    hue_range: Option<(f32, f32)>,
+}
+
+impl TransformEntry {
+    fn to_hsl_transform(&self) -> HslTransform {
+        HslTransform {
+            hue_rotate: self.hue_rotate,
+            saturation: self.saturation,
+            lightness: self.lightness,
+            hue_range: self.hue_range,
+        }
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct OutputEntry {
+    /// Output path template, see `template::expand` for the placeholders
+    path: String,
+    #[serde(default = "default_scale")]
+    scale: f32,
+}
+
+/// Settings shared by all file entries
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Defaults {
+    tile: Option<TileEntry>,
+    borders: Option<BordersEntry>,
+    variant: Option<String>,
+    #[serde(default)]
+    mappings: BTreeMap<String, String>,
+    #[serde(default)]
+    mapping_files: Vec<PathBuf>,
+    outputs: Option<Vec<OutputEntry>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileEntry {
+    /// Path, directory or glob pattern of the input SVGs
+    input: String,
+    /// Overrides the default tile setting
+    tile: Option<TileEntry>,
+    /// Overrides the default border, or switches it off with `"none"`
+    borders: Option<BordersEntry>,
+    /// Overrides the default variant name
+    variant: Option<String>,
+    /// Merged on top of the default mappings
+    #[serde(default)]
+    mappings: BTreeMap<String, String>,
+    /// Merged on top of the default mappings, before the entry's own mappings
+    #[serde(default)]
+    mapping_files: Vec<PathBuf>,
+    /// Replaces the default outputs
+    outputs: Option<Vec<OutputEntry>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    fonts: Option<PathBuf>,
+    /// Palette that mapping targets are looked up in
+    palette: Option<PathBuf>,
+    #[serde(default)]
+    all_input_colors: bool,
+    #[serde(default)]
+    all_svg_colors: bool,
+    #[serde(default)]
+    include_alpha: bool,
+    #[serde(default)]
+    paint_only: bool,
+    transform: Option<TransformEntry>,
+    tolerance: Option<ToleranceEntry>,
+    /// How scaled bounds are rounded, e.g. `bounds_rounding = "round"`
+    #[serde(default)]
+    bounds_rounding: Rounding,
+    #[serde(default)]
+    defaults: Defaults,
+    files: Vec<FileEntry>,
+}
+
+/// A build manifest, resolved into the same tasks that the `render` command accepts.
+/// All paths in the manifest are relative to the manifest's directory.
+pub struct Manifest {
+    pub fonts: Option<PathBuf>,
+    pub options: RenderOptions,
+    pub tasks: Vec<RenderTask>,
+    /// Palette and mapping files used by the manifest
+    pub sources: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// Load a manifest from a TOML file, or a JSON file if the extension is `.json`
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let text =
+            fs::read_to_string(path).map_err(|err| ManifestError::Read(path.to_owned(), err))?;
+
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let file: ManifestFile = if is_json {
+            serde_json::from_str(&text)?
+        } else {
+            toml::from_str(&text)?
+        };
+
+        Self::resolve(file, path)
+    }
+
+    fn resolve(file: ManifestFile, path: &Path) -> Result<Self, ManifestError> {
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut sources = Vec::new();
+        let palette = match &file.palette {
+            Some(palette_path) => {
+                let palette_path = base_dir.join(palette_path);
+                let palette = Palette::load(&palette_path)?;
+                sources.push(palette_path);
+                palette
+            }
+            None => Palette::default(),
+        };
+
+        // mapping files come first, so that mappings written in the manifest override them
+        let mut load_mappings = |files: &[PathBuf], mappings| {
+            let mut result = Vec::new();
+            for file in files {
+                let file = base_dir.join(file);
+                result.extend(palette.load_mappings(&file)?);
+                sources.push(file);
+            }
+            result.extend(palette.resolve_mappings(mappings, path)?);
+            Ok::<_, ManifestError>(result)
+        };
+
+        let default_mappings =
+            load_mappings(&file.defaults.mapping_files, &file.defaults.mappings)?;
+
+        let mut tasks = Vec::new();
+        for entry in &file.files {
+            let tile = entry.tile.as_ref().or(file.defaults.tile.as_ref());
+            let borders = entry.borders.as_ref().or(file.defaults.borders.as_ref());
+            let variant = entry.variant.as_ref().or(file.defaults.variant.as_ref());
+            let outputs = entry
+                .outputs
+                .as_ref()
+                .or(file.defaults.outputs.as_ref())
+                .filter(|outputs| !outputs.is_empty())
+                .ok_or_else(|| ManifestError::NoOutputs(entry.input.clone()))?;
+
+            // later mappings take priority, so entry mappings override the default mappings
+            let color_mappings: Vec<ColorMapping> = default_mappings
+                .iter()
+                .cloned()
+                .chain(load_mappings(&entry.mapping_files, &entry.mappings)?)
+                .collect();
+
+            for input in expand_input(&base_dir.join(&entry.input))? {
+                let outputs = outputs
+                    .iter()
+                    .map(|output| {
+                        let vars = TemplateVars {
+                            input: &input.path,
+                            rel_dir: &input.rel_dir,
+                            scale: output.scale,
+                            variant: variant.map(String::as_str),
+                        };
+                        Ok(Output {
+                            output: base_dir.join(template::expand(&output.path, &vars)?),
+                            scale: output.scale,
+                        })
+                    })
+                    .collect::<Result<_, ManifestError>>()?;
+
+                tasks.push(RenderTask {
+                    input: input.path,
+                    color_mappings: color_mappings.clone(),
+                    mapping_files: Vec::new(),
+                    tile_setting: tile.and_then(TileEntry::to_tile_setting),
+                    outputs,
+                    output_template: None,
+                    borders: borders.and_then(BordersEntry::to_reaper_bounds),
+                });
+            }
+        }
+
+        Ok(Self {
+            fonts: file.fonts.map(|path| base_dir.join(path)),
+            options: RenderOptions {
+                all_input_colors: file.all_input_colors,
+                all_svg_colors: file.all_svg_colors,
+                include_alpha: file.include_alpha,
+                paint_only: file.paint_only,
+                transform: file
+                    .transform
+                    .as_ref()
+                    .map_or_else(HslTransform::default, TransformEntry::to_hsl_transform),
+                tolerance: file.tolerance.as_ref().map(ToleranceEntry::to_tolerance),
+                bounds_rounding: file.bounds_rounding,
+            },
+            tasks,
+            sources,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::parser::Color;
+
+    use super::*;
+
+    fn resolve_toml(text: &str) -> Manifest {
+        let file: ManifestFile = toml::from_str(text).unwrap();
+        Manifest::resolve(file, Path::new("theme/manifest.toml")).unwrap()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let manifest = resolve_toml(
+            r##"
+            fonts = "fonts"
+
+            [defaults]
+            tile = "hb"
+            outputs = [
+                { path = "png/{stem}.png" },
+                { path = "png/200/{stem}.png", scale = 2 },
+            ]
+            mappings = { "#ff0000" = "#00ff00" }
+
+            [[files]]
+            input = "svg/button.svg"
+
+            [[files]]
+            input = "svg/knob.svg"
+            tile = "none"
+            mappings = { "#ff0000" = "#0000ff", "#111" = "#222" }
+            outputs = [{ path = "knob.png", scale = 1.5 }]
+            "##,
+        );
+
+        assert_eq!(manifest.fonts, Some(PathBuf::from("theme/fonts")));
+        assert_eq!(manifest.tasks.len(), 2);
+
+        let button = &manifest.tasks[0];
+        assert_eq!(button.input, PathBuf::from("theme/svg/button.svg"));
+        assert!(matches!(
+            button.tile_setting,
+            Some(TileSetting::HorizontalButton)
+        ));
+        assert_eq!(button.color_mappings.len(), 1);
+        assert_eq!(button.outputs.len(), 2);
+        assert_eq!(
+            button.outputs[1].output,
+            PathBuf::from("theme/png/200/button.png")
+        );
+        assert_eq!(button.outputs[1].scale, 2.0);
+
+        let knob = &manifest.tasks[1];
+        assert!(knob.tile_setting.is_none());
+        assert_eq!(knob.outputs.len(), 1);
+        assert_eq!(knob.outputs[0].output, PathBuf::from("theme/knob.png"));
+        let mappings: HashMap<_, _> = knob
+            .color_mappings
+            .iter()
+            .map(|cm| (cm.old.clone(), cm.new.clone()))
+            .collect();
+        assert_eq!(
+            mappings,
+            HashMap::from([
+                (Color::RGB(0x11, 0x11, 0x11), Color::RGB(0x22, 0x22, 0x22)),
+                (Color::RGB(255, 0, 0), Color::RGB(0, 0, 255)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_tile_settings() {
+        let manifest = resolve_toml(
+            r#"
+            [defaults]
+            outputs = [{ path = "{stem}.png" }]
+
+            [[files]]
+            input = "a.svg"
+            tile = { tx = 2, ty = 3 }
+
+            [[files]]
+            input = "b.svg"
+            tile = { ty = 4 }
+            "#,
+        );
+
+        assert!(matches!(
+            manifest.tasks[0].tile_setting,
+            Some(TileSetting::Grid { tx, ty }) if tx.get() == 2 && ty.get() == 3
+        ));
+        assert!(matches!(
+            manifest.tasks[1].tile_setting,
+            Some(TileSetting::VerticalTiles { ty }) if ty.get() == 4
+        ));
+
+        for tile in ["{ tx = 2, tz = 3 }", "{ tz = 3 }", "{}", "\"hv\""] {
+            let text = format!("[[files]]\ninput = \"a.svg\"\ntile = {}\n", tile);
+            assert!(toml::from_str::<ManifestFile>(&text).is_err(), "{}", tile);
+        }
+    }
+
+    #[test]
+    fn test_borders() {
+        let manifest = resolve_toml(
+            r#"
+            [defaults]
+            outputs = [{ path = "{stem}.png" }]
+            borders = { pink = [4, 0, 4, 0] }
+
+            [[files]]
+            input = "a.svg"
+
+            [[files]]
+            input = "b.svg"
+            borders = { yellow = [1, 1, 1, 1] }
+
+            [[files]]
+            input = "c.svg"
+            borders = "none"
+            "#,
+        );
+
+        let borders = manifest.tasks[0].borders.as_ref().unwrap();
+        assert_eq!((borders.pink.l, borders.pink.r), (4, 4));
+        assert!(borders.yellow.is_empty());
+        let borders = manifest.tasks[1].borders.as_ref().unwrap();
+        assert!(borders.pink.is_empty());
+        assert_eq!(borders.yellow.t, 1);
+        assert!(manifest.tasks[2].borders.is_none());
+
+        for borders in ["{ pinc = [1, 0, 1, 0] }", "\"off\""] {
+            let text = format!("[[files]]\ninput = \"a.svg\"\nborders = {}\n", borders);
+            assert!(
+                toml::from_str::<ManifestFile>(&text).is_err(),
+                "{}",
+                borders
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_outputs() {
+        let file: ManifestFile = toml::from_str(
+            r#"
+            [[files]]
+            input = "a.svg"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            Manifest::resolve(file, Path::new("manifest.toml")),
+            Err(ManifestError::NoOutputs(_))
+        ));
+    }
+}
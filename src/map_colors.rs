@@ -1,44 +1,215 @@
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
+    fmt,
+    ops::Range,
 };
 
+use serde::Serialize;
+
 use crate::{
+    cli::Tolerance,
+    delta_e::ciede2000,
+    notation::format_color,
+    options::RenderOptions,
+    paint,
     parser::{self, Color},
-    RenderOptions,
 };
 
-pub fn get_colors(xml: &str, include_alpha: bool) -> Result<HashSet<Color>, String> {
-    let mut result = HashSet::new();
-    for part in parser::xml_text(xml.into(), include_alpha).map_err(|x| format!("{}", x))? {
-        let parser::TextElement::Color(color) = part else {
+/// Split the SVG into colors and text, see `paint` for the difference between the two modes
+fn text_elements(
+    xml: &str,
+    include_alpha: bool,
+    paint_only: bool,
+) -> Result<Vec<parser::TextElement<'_>>, String> {
+    let result = if paint_only {
+        paint::paint_text(xml, include_alpha)
+    } else {
+        parser::xml_text(xml, include_alpha)
+            .map(|elements| paint::with_paint_contexts(xml, elements, include_alpha))
+    };
+    result.map_err(|err| {
+        let (line, column) = line_column(xml, offset_in(xml, err.input));
+        // the color function that failed, or the start of the text
+        let text = match err.input.find(')') {
+            Some(end) if end < 80 => &err.input[..=end],
+            _ => &err.input[..err.input.floor_char_boundary(40)],
+        };
+        match err.code {
+            nom::error::ErrorKind::Verify => format!(
+                "{}:{}: the alpha of {} must be from 0 to 1 or 0% to 100%",
+                line, column, text
+            ),
+            _ => format!("{}:{}: failed to parse {:?}", line, column, text),
+        }
+    })
+}
+
+/// Byte offset of `text`, a slice of `xml`, in `xml`
+fn offset_in(xml: &str, text: &str) -> usize {
+    text.as_ptr() as usize - xml.as_ptr() as usize
+}
+
+/// 1-based line and column, in characters, of a byte offset
+fn line_column(xml: &str, offset: usize) -> (usize, usize) {
+    let before = &xml[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Where a color is used in an SVG
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ColorLocation {
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    /// Id of the element the color is in, if it has one
+    pub id: Option<String>,
+}
+
+impl fmt::Display for ColorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)?;
+        if let Some(id) = &self.id {
+            write!(f, " #{}", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Id of the element whose start tag contains `offset`, given the start tags in order
+fn element_id(tags: &[(Range<usize>, paint::Attributes)], offset: usize) -> Option<String> {
+    // a color after the last tag that ends before it, e.g. in a <style> element, has no id
+    let (span, attributes) = tags.get(tags.partition_point(|(span, _)| span.end <= offset))?;
+    if !span.contains(&offset) {
+        return None;
+    }
+    attributes
+        .iter()
+        .find(|(name, _)| *name == "id")
+        .map(|(_, value)| (*value).to_owned())
+}
+
+/// List the colors in the SVG, in order, along with where they are
+pub fn get_color_locations(
+    xml: &str,
+    include_alpha: bool,
+    paint_only: bool,
+) -> Result<Vec<(Color, ColorLocation)>, String> {
+    let tags = paint::start_tags(xml);
+    let mut result = Vec::new();
+    for part in text_elements(xml, include_alpha, paint_only)? {
+        let parser::TextElement::Color(color, text, _) = part else {
             continue;
         };
-        result.insert(color);
+        // the text is a slice of the SVG
+        let offset = offset_in(xml, text);
+        let (line, column) = line_column(xml, offset);
+        let location = ColorLocation {
+            line,
+            column,
+            id: element_id(&tags, offset),
+        };
+        result.push((color, location));
     }
     Ok(result)
 }
 
+impl Tolerance {
+    /// Whether a color is close enough to the source color of a mapping
+    pub fn matches(&self, source: &Color, color: &Color) -> bool {
+        match *self {
+            Tolerance::Channel { max_difference } => {
+                let channels = |c: &Color| [c.r(), c.g(), c.b(), c.a().unwrap_or(255)];
+                channels(source)
+                    .into_iter()
+                    .zip(channels(color))
+                    .all(|(a, b)| a.abs_diff(b) <= max_difference)
+            }
+            Tolerance::DeltaE { max_delta_e } => {
+                source.a() == color.a() && ciede2000(source, color) <= max_delta_e
+            }
+        }
+    }
+}
+
+/// An SVG color that was mapped because it is within the tolerance of a mapping
+#[derive(Debug, PartialEq)]
+pub struct NearMatch {
+    pub color: Color,
+    /// Source color of the mapping
+    pub source: Color,
+    pub new: Color,
+}
+
+/// An SVG with its colors replaced
+#[derive(Debug)]
+pub struct MappedSvg {
+    pub text: String,
+    pub near_matches: Vec<NearMatch>,
+}
+
+/// Find the mapping of a color, matching exactly before trying the tolerance. Colors within
+/// the tolerance of several mappings are an error, since it isn't clear which one to use.
+fn find_mapping<'a>(
+    color: &Color,
+    color_map: &'a HashMap<Color, Color>,
+    tolerance: Option<Tolerance>,
+) -> Result<Option<(&'a Color, &'a Color)>, String> {
+    if let Some(mapping) = color_map.get_key_value(color) {
+        return Ok(Some(mapping));
+    }
+    let Some(tolerance) = tolerance else {
+        return Ok(None);
+    };
+
+    let mut matches = color_map
+        .iter()
+        .filter(|(source, _)| tolerance.matches(source, color));
+    match (matches.next(), matches.next()) {
+        (Some((a, _)), Some((b, _))) => Err(format!(
+            "failed to map color {} - it is within the tolerance of both {} and {}",
+            color, a, b
+        )),
+        (mapping, _) => Ok(mapping),
+    }
+}
+
+/// Replace colors in the SVG. Colors without an explicit mapping go through the HSL
+/// transform instead. REAPER's reserved colors and colors that aren't changed are left as
+/// they were written.
 pub fn map_colors(
     xml: &str,
     color_map: &HashMap<Color, Color>,
     opt: &RenderOptions,
-) -> Result<String, String> {
+) -> Result<MappedSvg, String> {
     let mut unused_colors: HashSet<Color> = color_map.keys().cloned().collect();
-    let result: Result<String, String> = parser::xml_text(xml.into(), opt.include_alpha)
-        .map_err(|x| format!("{}", x))?
+    let mut near_matches = Vec::new();
+    let result: Result<String, String> = text_elements(xml, opt.include_alpha, opt.paint_only)?
         .iter()
         .map(|part| match part {
             parser::TextElement::Text(text) => Ok(Cow::from(*text)),
-            parser::TextElement::Color(old_color) => {
+            parser::TextElement::Color(old_color, old_text, context) => {
                 if old_color.is_reaper_reserved() {
-                    return Ok(old_color.to_string().into());
+                    return Ok(Cow::from(*old_text));
                 }
 
-                match color_map.get(old_color) {
-                    Some(new_color) => {
-                        unused_colors.remove(old_color);
-                        Ok(new_color.to_string().into())
+                match find_mapping(old_color, color_map, opt.tolerance)? {
+                    Some((source, new_color)) => {
+                        unused_colors.remove(source);
+                        let near_match = NearMatch {
+                            color: old_color.clone(),
+                            source: source.clone(),
+                            new: new_color.clone(),
+                        };
+                        if source != old_color && !near_matches.contains(&near_match) {
+                            near_matches.push(near_match);
+                        }
+                        Ok(format_color(new_color, old_text, *context).into())
                     }
                     None => {
                         if opt.all_svg_colors {
@@ -46,20 +217,176 @@ pub fn map_colors(
                                 "failed to map colors {:?} - svg color not in map",
                                 old_color
                             ))
+                        } else if !opt.transform.is_identity() {
+                            Ok(
+                                format_color(&opt.transform.apply(old_color), old_text, *context)
+                                    .into(),
+                            )
                         } else {
-                            Ok(old_color.to_string().into())
+                            Ok(Cow::from(*old_text))
                         }
                     }
                 }
             }
         })
         .collect();
-    let result = result?;
-    if opt.all_input_colors && unused_colors.len() != 0 {
+    let text = result?;
+    if opt.all_input_colors && !unused_colors.is_empty() {
         return Err(format!(
             "failed to map colors {:?} - colors not found in svg",
             unused_colors
         ));
     }
-    Ok(result)
+    Ok(MappedSvg { text, near_matches })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_locations() {
+        let xml =
+            "<svg>\n  <rect id=\"bg\" fill=\"#fff\"/>\n  <style>a { fill: #000 }</style>\n</svg>";
+        let location = |line, column, id: Option<&str>| ColorLocation {
+            line,
+            column,
+            id: id.map(str::to_owned),
+        };
+        assert_eq!(
+            get_color_locations(xml, false, true).unwrap(),
+            vec![
+                (Color::RGB(255, 255, 255), location(2, 23, Some("bg"))),
+                (Color::RGB(0, 0, 0), location(3, 20, None)),
+            ]
+        );
+
+        // opacity attributes are folded into the alpha in both modes
+        let xml = r##"<path fill="#fff" fill-opacity="0.5" opacity="0.5"/>"##;
+        for paint_only in [false, true] {
+            let colors = get_color_locations(xml, true, paint_only).unwrap();
+            assert_eq!(colors[0].0, Color::RGBA(255, 255, 255, 64));
+        }
+
+        // only the id attribute itself counts, wherever it is in the tag
+        let xml = concat!(
+            r##"<!-- <g id="comment"> --><rect data-id="x" serif:id="Layer 1" "##,
+            r##"aria-label="a > b" id="bg" fill="#fff"/><rect data-id="y" fill="#000"/>"##
+        );
+        let ids: Vec<_> = get_color_locations(xml, false, false)
+            .unwrap()
+            .into_iter()
+            .map(|(_, location)| location.id)
+            .collect();
+        assert_eq!(ids, vec![Some("bg".into()), None]);
+
+        let xml = "<svg>\n  <rect fill=\"rgba(1, 2, 3, 255)\"/>\n</svg>";
+        for paint_only in [false, true] {
+            assert_eq!(
+                get_color_locations(xml, false, paint_only).unwrap_err(),
+                "2:15: the alpha of rgba(1, 2, 3, 255) must be from 0 to 1 or 0% to 100%"
+            );
+        }
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let opt = RenderOptions {
+            all_input_colors: false,
+            all_svg_colors: false,
+            include_alpha: false,
+            paint_only: true,
+            transform: Default::default(),
+            tolerance: Some(Tolerance::Channel { max_difference: 1 }),
+            bounds_rounding: Default::default(),
+        };
+        let color_map = HashMap::from([
+            (Color::RGB(255, 0, 0), Color::RGB(0, 0, 255)),
+            (Color::RGB(0, 0, 0), Color::RGB(0, 255, 0)),
+        ]);
+
+        let mapped = map_colors(
+            r##"<a fill="#f00" stroke="#fe0101"/><b fill="#fe0101"/>"##,
+            &color_map,
+            &opt,
+        )
+        .unwrap();
+        assert_eq!(
+            mapped.text,
+            r##"<a fill="#00f" stroke="#0000ff"/><b fill="#0000ff"/>"##
+        );
+        assert_eq!(
+            mapped.near_matches,
+            vec![NearMatch {
+                color: Color::RGB(254, 1, 1),
+                source: Color::RGB(255, 0, 0),
+                new: Color::RGB(0, 0, 255),
+            }]
+        );
+
+        // within the tolerance of two mappings
+        let color_map = HashMap::from([
+            (Color::RGB(0, 0, 0), Color::RGB(0, 0, 255)),
+            (Color::RGB(2, 2, 2), Color::RGB(0, 255, 0)),
+        ]);
+        assert!(map_colors(r##"<a fill="#010101"/>"##, &color_map, &opt).is_err());
+        assert!(map_colors(r##"<a fill="#020202"/>"##, &color_map, &opt).is_ok());
+
+        let delta_e = Tolerance::DeltaE { max_delta_e: 1.0 };
+        assert!(delta_e.matches(&Color::RGB(0x33, 0x33, 0x33), &Color::RGB(0x34, 0x34, 0x34)));
+        assert!(!delta_e.matches(&Color::RGB(0x33, 0x33, 0x33), &Color::RGB(0x40, 0x33, 0x33)));
+    }
+
+    #[test]
+    fn test_opacity_attributes() {
+        let color_map = HashMap::from([
+            (Color::RGB(0, 0, 0), Color::RGBA(255, 0, 0, 128)),
+            (Color::RGB(0, 0, 255), Color::RGBA(0, 255, 0, 64)),
+        ]);
+        let svg = r##"<path stroke="#000"/><stop stop-color="#00f"/><path stroke="#000" stroke-opacity="1"/>"##;
+        // translucent strokes and stops get an opacity attribute in both modes
+        for paint_only in [false, true] {
+            let opt = RenderOptions {
+                all_input_colors: false,
+                all_svg_colors: false,
+                include_alpha: false,
+                paint_only,
+                transform: Default::default(),
+                tolerance: None,
+                bounds_rounding: Default::default(),
+            };
+            assert_eq!(
+                map_colors(svg, &color_map, &opt).unwrap().text,
+                concat!(
+                    r##"<path stroke="#f00" stroke-opacity="0.502"/>"##,
+                    r##"<stop stop-color="#0f0" stop-opacity="0.251"/>"##,
+                    r##"<path stroke="#ff000080" stroke-opacity="1"/>"##,
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_element_opacity() {
+        let color_map =
+            HashMap::from([(Color::RGBA(255, 255, 255, 128), Color::RGBA(0, 0, 0, 128))]);
+        // the element's opacity stays in place, in both modes
+        for paint_only in [false, true] {
+            let opt = RenderOptions {
+                all_input_colors: false,
+                all_svg_colors: false,
+                include_alpha: true,
+                paint_only,
+                transform: Default::default(),
+                tolerance: None,
+                bounds_rounding: Default::default(),
+            };
+            assert_eq!(
+                map_colors(r##"<path fill="#fff" opacity="0.5"/>"##, &color_map, &opt)
+                    .unwrap()
+                    .text,
+                r##"<path fill="#000" opacity="0.5"/>"##
+            );
+        }
+    }
 }
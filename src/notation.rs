@@ -0,0 +1,331 @@
+//! Writing a replacement color in the same notation as the color it replaces, so that
+//! mapped SVGs only differ where colors changed and stay valid in their context.
+
+use crate::{
+    hsl::{hsl_to_rgb, rgb_to_hsl},
+    named_colors::NAMED_COLORS,
+    parser::{Color, ColorContext, PropertyForm},
+};
+
+#[derive(PartialEq, Debug)]
+enum Notation {
+    Hex {
+        prefix: &'static str,
+        short: bool,
+        upper: bool,
+    },
+    /// `legacy` is the comma-separated syntax, e.g. `rgb(1, 2, 3)` instead of `rgb(1 2 3)`
+    Rgb {
+        legacy: bool,
+    },
+    Hsl {
+        legacy: bool,
+    },
+    Named,
+}
+
+/// Split an opacity declaration such as `;fill-opacity:0.5` from the color text, returning
+/// the name of the opacity property
+fn split_opacity(source: &str) -> (&str, Option<&str>) {
+    match source.split_once(';') {
+        Some((color, suffix)) => {
+            let name = suffix.split(':').next().unwrap_or_default().trim();
+            (color, Some(name))
+        }
+        None => (source, None),
+    }
+}
+
+fn detect(source: &str) -> Notation {
+    let lower = source.to_ascii_lowercase();
+    let hex = |prefix: &'static str, digits: &str| Notation::Hex {
+        prefix,
+        short: digits.len() <= 4,
+        upper: digits.chars().any(|c| c.is_ascii_uppercase()),
+    };
+    if let Some(digits) = source.strip_prefix('#') {
+        hex("#", digits)
+    } else if let Some(digits) = source.strip_prefix("0x") {
+        hex("0x", digits)
+    } else if lower.starts_with("rgb") {
+        Notation::Rgb {
+            legacy: source.contains(','),
+        }
+    } else if lower.starts_with("hsl") {
+        Notation::Hsl {
+            legacy: source.contains(','),
+        }
+    } else {
+        Notation::Named
+    }
+}
+
+/// An opacity with at most 3 decimals, e.g. `0.5`
+fn format_opacity(a: u8) -> String {
+    let text = format!("{:.3}", a as f32 / 255.0);
+    text.trim_end_matches('0').trim_end_matches('.').into()
+}
+
+/// A number with as few decimals as possible, up to `decimals`
+fn format_number(value: f32, decimals: usize) -> String {
+    let text = format!("{:.*}", decimals, value);
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').into()
+    } else {
+        text
+    }
+}
+
+fn format_hex(channels: &[u8], prefix: &str, short: bool, upper: bool) -> String {
+    let short = short && channels.iter().all(|c| c >> 4 == c & 0xf);
+    let digits: String = channels
+        .iter()
+        .map(|c| match short {
+            true => format!("{:x}", c & 0xf),
+            false => format!("{:02x}", c),
+        })
+        .collect();
+    match upper {
+        true => format!("{}{}", prefix, digits.to_ascii_uppercase()),
+        false => format!("{}{}", prefix, digits),
+    }
+}
+
+/// A color as `#rrggbb`, or `#rrggbbaa` if it has an alpha
+pub fn to_hex(color: &Color) -> String {
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    match color.a() {
+        Some(a) => format_hex(&[r, g, b, a], "#", false, false),
+        None => format_hex(&[r, g, b], "#", false, false),
+    }
+}
+
+/// HSL components with the fewest decimals that still round-trip to the same color
+fn hsl_components(r: u8, g: u8, b: u8) -> [String; 3] {
+    let (h, s, l) = rgb_to_hsl(r, g, b);
+    let (s, l) = (s * 100.0, l * 100.0);
+    let decimals = (0..3)
+        .find(|&decimals| {
+            let round = |v: f32| format_number(v, decimals).parse::<f32>().unwrap();
+            hsl_to_rgb(round(h), round(s) / 100.0, round(l) / 100.0) == (r, g, b)
+        })
+        .unwrap_or(3);
+    [
+        format_number(h, decimals),
+        format!("{}%", format_number(s, decimals)),
+        format!("{}%", format_number(l, decimals)),
+    ]
+}
+
+/// Write a color in a notation. `alpha` is `None` if the alpha is written elsewhere.
+fn format_notation(notation: &Notation, color: &Color, alpha: Option<u8>) -> String {
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    match *notation {
+        Notation::Hex {
+            prefix,
+            short,
+            upper,
+        } => match alpha {
+            Some(a) => format_hex(&[r, g, b, a], prefix, short, upper),
+            None => format_hex(&[r, g, b], prefix, short, upper),
+        },
+        Notation::Rgb { legacy: true } => match alpha {
+            Some(a) => format!("rgba({}, {}, {}, {})", r, g, b, format_opacity(a)),
+            None => format!("rgb({}, {}, {})", r, g, b),
+        },
+        Notation::Rgb { legacy: false } => match alpha {
+            Some(a) => format!("rgb({} {} {} / {})", r, g, b, format_opacity(a)),
+            None => format!("rgb({} {} {})", r, g, b),
+        },
+        Notation::Hsl { legacy } => {
+            let [h, s, l] = hsl_components(r, g, b);
+            match (alpha, legacy) {
+                (Some(a), true) => format!("hsla({}, {}, {}, {})", h, s, l, format_opacity(a)),
+                (Some(a), false) => format!("hsl({} {} {} / {})", h, s, l, format_opacity(a)),
+                (None, true) => format!("hsl({}, {}, {})", h, s, l),
+                (None, false) => format!("hsl({} {} {})", h, s, l),
+            }
+        }
+        Notation::Named => {
+            let name = NAMED_COLORS
+                .iter()
+                .find(|(_, rgb)| *rgb == [r, g, b])
+                .map(|(name, _)| *name);
+            match (name, alpha) {
+                (Some(name), None) => name.into(),
+                // named colors can't have an alpha, fall back to hex
+                _ => format_notation(
+                    &Notation::Hex {
+                        prefix: "#",
+                        short: false,
+                        upper: false,
+                    },
+                    color,
+                    alpha,
+                ),
+            }
+        }
+    }
+}
+
+/// Write `color` to replace the color text `source`, which was found in `context`. The
+/// notation of the source is kept. An alpha is written to the opacity property of the
+/// color when possible, e.g. `stroke-opacity` for strokes, and in the color otherwise.
+pub fn format_color(color: &Color, source: &str, context: ColorContext) -> String {
+    let (source, suffix_property) = split_opacity(source);
+    let notation = detect(source);
+
+    let Some(a) = color.a() else {
+        return format_notation(&notation, color, None);
+    };
+    if let Some(opacity) = context.opacity {
+        // the opacity properties stay in place, only the rest of the alpha is written
+        let a = (a as f32 * 255.0 / opacity.max(1) as f32)
+            .round()
+            .min(255.0) as u8;
+        return match a {
+            255 => format_notation(&notation, color, None),
+            a => format_notation(&notation, color, Some(a)),
+        };
+    }
+
+    let opacity_property = context.property.and_then(|p| p.opacity_property());
+    match (context.form, opacity_property) {
+        (PropertyForm::Declaration, Some(property)) => format!(
+            "{};{}:{}",
+            format_notation(&notation, color, None),
+            property,
+            format_opacity(a)
+        ),
+        (
+            PropertyForm::Attribute {
+                quote,
+                add_opacity: true,
+            },
+            Some(property),
+        ) => format!(
+            "{}{} {}={}{}",
+            format_notation(&notation, color, None),
+            quote,
+            property,
+            quote,
+            format_opacity(a)
+        ),
+        (PropertyForm::Unknown, _) => match suffix_property {
+            Some(property) => format!(
+                "{};{}:{}",
+                format_notation(&notation, color, None),
+                property,
+                format_opacity(a)
+            ),
+            None => format_notation(&notation, color, Some(a)),
+        },
+        _ => format_notation(&notation, color, Some(a)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ColorProperty;
+
+    use super::*;
+
+    const UNKNOWN: ColorContext = ColorContext {
+        property: None,
+        form: PropertyForm::Unknown,
+        opacity: None,
+    };
+
+    #[test]
+    fn test_notation() {
+        let color = Color::RGB(0x66, 0x99, 0xcc);
+        let format = |source| format_color(&color, source, UNKNOWN);
+        assert_eq!(format("#fff"), "#69c");
+        assert_eq!(format("#ABCDEF"), "#6699CC");
+        assert_eq!(format("0x000000"), "0x6699cc");
+        assert_eq!(format("rgb(1, 2, 3)"), "rgb(102, 153, 204)");
+        assert_eq!(format("RGB(1 2 3)"), "rgb(102 153 204)");
+        assert_eq!(format("hsl(0, 100%, 50%)"), "hsl(210, 50%, 60%)");
+        assert_eq!(format("red"), "#6699cc");
+        assert_eq!(format_color(&Color::RGB(0, 0, 255), "red", UNKNOWN), "blue");
+        assert_eq!(
+            format_color(&Color::RGB(1, 2, 3), "#fff", UNKNOWN),
+            "#010203"
+        );
+    }
+
+    #[test]
+    fn test_alpha() {
+        let color = Color::RGBA(255, 0, 0, 128);
+        let context = |property, form| ColorContext {
+            property: Some(property),
+            form,
+            opacity: None,
+        };
+        let attribute = |add_opacity| PropertyForm::Attribute {
+            quote: '"',
+            add_opacity,
+        };
+
+        assert_eq!(format_color(&color, "#00f", UNKNOWN), "#ff000080");
+        assert_eq!(
+            format_color(&color, "rgb(0, 0, 0)", UNKNOWN),
+            "rgba(255, 0, 0, 0.502)"
+        );
+        assert_eq!(
+            format_color(&color, "#000;fill-opacity:0.2", UNKNOWN),
+            "#f00;fill-opacity:0.502"
+        );
+        assert_eq!(
+            format_color(
+                &color,
+                "blue",
+                context(ColorProperty::Stroke, PropertyForm::Declaration)
+            ),
+            "red;stroke-opacity:0.502"
+        );
+        assert_eq!(
+            format_color(
+                &color,
+                "#000",
+                context(ColorProperty::StopColor, attribute(true))
+            ),
+            r##"#f00" stop-opacity="0.502"##
+        );
+        assert_eq!(
+            format_color(
+                &color,
+                "#000",
+                context(ColorProperty::Fill, attribute(false))
+            ),
+            "#ff000080"
+        );
+        assert_eq!(
+            format_color(&Color::RGBA(255, 0, 0, 0x88), "#000", UNKNOWN),
+            "#f008"
+        );
+        assert_eq!(
+            format_color(
+                &color,
+                "#000",
+                context(ColorProperty::Color, PropertyForm::Declaration)
+            ),
+            "#ff000080"
+        );
+
+        // an opacity of 0.5 is kept in an attribute, only the rest is written
+        let external = ColorContext {
+            opacity: Some(128),
+            ..context(ColorProperty::Fill, attribute(true))
+        };
+        assert_eq!(format_color(&color, "#000", external), "#f00");
+        assert_eq!(
+            format_color(&Color::RGBA(255, 0, 0, 64), "#000", external),
+            "#ff000080"
+        );
+        assert_eq!(
+            format_color(&color, "#000;stroke-opacity:1", UNKNOWN),
+            "#f00;stroke-opacity:0.502"
+        );
+    }
+}
@@ -0,0 +1,16 @@
+//! Options shared by the commands that map colors and render SVGs
+
+use crate::{
+    bounds::Rounding,
+    cli::{HslTransform, Tolerance},
+};
+
+pub struct RenderOptions {
+    pub all_input_colors: bool,
+    pub all_svg_colors: bool,
+    pub include_alpha: bool,
+    pub paint_only: bool,
+    pub transform: HslTransform,
+    pub tolerance: Option<Tolerance>,
+    pub bounds_rounding: Rounding,
+}
@@ -0,0 +1,559 @@
+//! XML-aware color parsing. Unlike `parser::xml_text`, which finds colors anywhere in the
+//! text, this only finds colors in paint properties: the `fill`, `stroke`, `stop-color`,
+//! `flood-color`, `lighting-color` and `color` attributes, and declarations of these
+//! properties in `style` attributes and `<style>` elements. Everything else, such as ids,
+//! `href="#..."` references, comments and text content, is returned as text.
+
+use std::{collections::HashMap, ops::Range};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case, take, take_till, take_till1, take_until, take_while1},
+    character::complete::{char, multispace0, multispace1, none_of, one_of},
+    combinator::{all_consuming, consumed, eof, not, opt, recognize},
+    multi::{fold_many0, many0},
+    sequence::{delimited, preceded, tuple},
+    Finish, Parser,
+};
+
+use crate::parser::{
+    color, color_property_name, identifier, opacity, with_opacity, ColorContext, ColorProperty,
+    Input, PropertyForm, Result, TextElement,
+};
+
+type Elements<'a> = Vec<TextElement<'a>>;
+
+/// Attributes of a start tag, as names and raw values
+pub type Attributes<'a> = Vec<(&'a Input, &'a Input)>;
+
+/// Opacity properties, in the order they were declared. The property is `None` for the
+/// `opacity` of an element.
+type Opacities = Vec<(Option<ColorProperty>, f32)>;
+
+fn text(text: &Input) -> Elements<'_> {
+    vec![TextElement::Text(text)]
+}
+
+fn extend<'a>(mut result: Elements<'a>, elements: Elements<'a>) -> Elements<'a> {
+    result.extend(elements);
+    result
+}
+
+fn extend_both<'a>(
+    (mut elements, mut opacities): (Elements<'a>, Opacities),
+    (more_elements, more_opacities): (Elements<'a>, Opacities),
+) -> (Elements<'a>, Opacities) {
+    elements.extend(more_elements);
+    opacities.extend(more_opacities);
+    (elements, opacities)
+}
+
+/// The last declared opacity of a property
+fn find_opacity(opacities: &Opacities, property: Option<ColorProperty>) -> Option<f32> {
+    opacities
+        .iter()
+        .rev()
+        .find(|(other, _)| *other == property)
+        .map(|(_, opacity)| *opacity)
+}
+
+/// Fold opacity properties into the alpha of the colors they apply to, e.g. a
+/// `stroke-opacity` into the stroke and an element's `opacity` into all of its colors
+fn apply_opacities(elements: &mut Elements, opacities: &Opacities) {
+    let element_opacity = find_opacity(opacities, None);
+    for element in elements {
+        let TextElement::Color(color, _, context) = element else {
+            continue;
+        };
+        let property_opacity = context
+            .property
+            .and_then(|property| find_opacity(opacities, Some(property)));
+        let opacity = match (property_opacity, element_opacity) {
+            (None, None) => continue,
+            (a, b) => a.unwrap_or(1.0) * b.unwrap_or(1.0),
+        };
+        *color = color.with_opacity(opacity);
+        context.opacity = Some((opacity * 255.0).round() as u8);
+    }
+}
+
+/// Name of an opacity property
+fn opacity_property_name(input: &Input) -> Result<'_, Option<ColorProperty>> {
+    alt((
+        tag("fill-opacity").map(|_| Some(ColorProperty::Fill)),
+        tag("stroke-opacity").map(|_| Some(ColorProperty::Stroke)),
+        tag("stop-opacity").map(|_| Some(ColorProperty::StopColor)),
+        tag("flood-opacity").map(|_| Some(ColorProperty::FloodColor)),
+        tag("opacity").map(|_| None),
+    ))(input)
+}
+
+/// Colors in the value of a paint property. `url()` references are skipped, so that
+/// an id like `url(#abc123)` isn't mistaken for a color.
+fn paint_value(
+    input: &Input,
+    parse_opacity: bool,
+    context: ColorContext,
+) -> Result<'_, Elements<'_>> {
+    fold_many0(
+        alt((
+            consumed(with_opacity(color, context.property, parse_opacity))
+                .map(|(text, color)| vec![TextElement::Color(color, text, context)]),
+            recognize(tuple((tag_no_case("url("), take_till(|c| c == ')')))).map(text),
+            identifier.map(text),
+            recognize(none_of(";}")).map(text),
+        )),
+        Vec::new,
+        extend,
+    )(input)
+}
+
+/// A paint property declaration, e.g. `fill: #fff`
+fn css_declaration(input: &Input, parse_opacity: bool) -> Result<'_, Elements<'_>> {
+    let (input, (text, (property, ..))) = consumed(tuple((
+        color_property_name,
+        multispace0,
+        char(':'),
+        multispace0,
+    )))(input)?;
+    let context = ColorContext {
+        property: Some(property),
+        form: PropertyForm::Declaration,
+        opacity: None,
+    };
+    let (input, value) = paint_value(input, parse_opacity, context)?;
+    Ok((input, extend(vec![TextElement::Text(text)], value)))
+}
+
+/// An opacity declaration, e.g. `stroke-opacity: 0.5`
+fn css_opacity(input: &Input) -> Result<'_, (Elements<'_>, Opacities)> {
+    consumed(tuple((
+        opacity_property_name,
+        multispace0,
+        char(':'),
+        multispace0,
+        opacity,
+    )))
+    .map(|(declaration, (property, _, _, _, opacity))| {
+        (text(declaration), vec![(property, opacity)])
+    })
+    .parse(input)
+}
+
+/// CSS declarations up to the end of a rule, along with the opacities declared in them.
+/// Words are consumed whole, so that properties like `outline-color` aren't mistaken for
+/// paint properties.
+fn css_declarations(input: &Input, parse_opacity: bool) -> Result<'_, (Elements<'_>, Opacities)> {
+    fold_many0(
+        alt((
+            recognize(tuple((tag("/*"), take_until("*/"), tag("*/"))))
+                .map(|comment| (text(comment), vec![])),
+            (|input| css_declaration(input, parse_opacity)).map(|elements| (elements, vec![])),
+            css_opacity,
+            identifier.map(|word| (text(word), vec![])),
+            recognize(none_of("}")).map(|other| (text(other), vec![])),
+        )),
+        Default::default,
+        extend_both,
+    )(input)
+}
+
+/// CSS in a `<style>` element. Opacities apply to the colors in the same rule.
+fn css_text(input: &Input, parse_opacity: bool) -> Result<'_, Elements<'_>> {
+    fold_many0(
+        preceded(
+            not(eof),
+            tuple((
+                |input| css_declarations(input, parse_opacity),
+                opt(recognize(char('}'))),
+            )),
+        )
+        .map(|((mut elements, opacities), end)| {
+            if parse_opacity {
+                apply_opacities(&mut elements, &opacities);
+            }
+            elements.extend(end.map(TextElement::Text));
+            elements
+        }),
+        Vec::new,
+        extend,
+    )(input)
+}
+
+fn xml_name(input: &Input) -> Result<'_> {
+    take_while1(|c: char| c.is_alphanumeric() || "-_:.".contains(c))(input)
+}
+
+/// An attribute split into the text up to its value, its name, its quote, its value and the
+/// closing quote
+fn attribute_parts(input: &Input) -> Result<'_, (&Input, &Input, char, &Input, &Input)> {
+    let (input, (prefix, (_, name, _, _, _, quote))) = consumed(tuple((
+        multispace1,
+        xml_name,
+        multispace0,
+        char('='),
+        multispace0,
+        one_of("\"'"),
+    )))(input)?;
+    let (input, value) = take_till(|c| c == quote)(input)?;
+    let (input, end) = recognize(char(quote))(input)?;
+    Ok((input, (prefix, name, quote, value, end)))
+}
+
+/// An attribute, along with the opacities set in it and whether it is the `style` attribute
+fn attribute(input: &Input, parse_opacity: bool) -> Result<'_, (Elements<'_>, Opacities, bool)> {
+    let (input, (prefix, name, quote, value, end)) = attribute_parts(input)?;
+
+    let mut elements = text(prefix);
+    let mut opacities = vec![];
+    if name == "style" {
+        let (rest, (value, style_opacities)) = css_declarations(value, parse_opacity)?;
+        elements.extend(value);
+        // a stray '}' ends the declarations early, keep the rest as it is
+        if !rest.is_empty() {
+            elements.push(TextElement::Text(rest));
+        }
+        opacities = style_opacities;
+    } else if let Ok((_, property)) = all_consuming(color_property_name)(name) {
+        let context = ColorContext {
+            property: Some(property),
+            form: PropertyForm::Attribute {
+                quote,
+                add_opacity: false,
+            },
+            opacity: None,
+        };
+        let (rest, mut value) = paint_value(value, parse_opacity, context)?;
+
+        // an opacity attribute can only be inserted after a color that ends the value
+        let ends_value = |element: &TextElement| match element {
+            TextElement::Text(text) => text.trim().is_empty(),
+            TextElement::Color(..) => false,
+        };
+        if let Some(TextElement::Color(_, _, context)) =
+            value.iter_mut().rev().find(|element| !ends_value(element))
+        {
+            if rest.is_empty() {
+                context.form = PropertyForm::Attribute {
+                    quote,
+                    add_opacity: true,
+                };
+            }
+        }
+
+        elements.extend(value);
+        if !rest.is_empty() {
+            elements.push(TextElement::Text(rest));
+        }
+    } else {
+        let opacity_value = delimited(multispace0, opacity, multispace0);
+        if let Ok((_, property)) = all_consuming(opacity_property_name)(name) {
+            if let Ok((_, opacity)) = all_consuming(opacity_value)(value) {
+                opacities.push((property, opacity));
+            }
+        }
+        elements.push(TextElement::Text(value));
+    }
+    elements.push(TextElement::Text(end));
+    Ok((input, (elements, opacities, name == "style")))
+}
+
+/// A start tag, and the contents of `<style>` elements
+fn element(input: &Input, parse_opacity: bool) -> Result<'_, Elements<'_>> {
+    let (input, (open, name)) = consumed(preceded(char('<'), xml_name))(input)?;
+    let (input, (mut attributes, attribute_opacities, style_opacities)) = fold_many0(
+        |input| attribute(input, parse_opacity),
+        Default::default,
+        |(mut elements, mut attributes, mut style): (Elements, Opacities, Opacities),
+         (more_elements, opacities, is_style)| {
+            elements.extend(more_elements);
+            match is_style {
+                true => style.extend(opacities),
+                false => attributes.extend(opacities),
+            }
+            (elements, attributes, style)
+        },
+    )(input)?;
+    let (input, (close, self_closing)) = consumed(tuple((multispace0, opt(char('/')), char('>'))))
+        .map(|(close, (_, slash, _))| (close, slash.is_some()))
+        .parse(input)?;
+
+    // the style attribute overrides presentation attributes
+    let opacities: Opacities = attribute_opacities
+        .into_iter()
+        .chain(style_opacities)
+        .collect();
+
+    // opacity attributes can't be added if the element already sets the opacity
+    for element in &mut attributes {
+        if let TextElement::Color(_, _, context) = element {
+            let has_opacity = context
+                .property
+                .is_some_and(|property| find_opacity(&opacities, Some(property)).is_some());
+            if let PropertyForm::Attribute { add_opacity, .. } = &mut context.form {
+                *add_opacity &= !has_opacity;
+            }
+        }
+    }
+    if parse_opacity {
+        apply_opacities(&mut attributes, &opacities);
+    }
+
+    let mut elements = extend(text(open), attributes);
+    elements.push(TextElement::Text(close));
+
+    let is_style = name.rsplit(':').next() == Some("style");
+    if !is_style || self_closing {
+        return Ok((input, elements));
+    }
+    match take_until::<_, _, nom::error::Error<_>>("</")(input) {
+        Ok((input, css)) => {
+            elements.extend(css_text(css, parse_opacity)?.1);
+            Ok((input, elements))
+        }
+        Err(_) => Ok((input, elements)),
+    }
+}
+
+/// Comments, CDATA sections, processing instructions, doctypes and end tags
+fn markup(input: &Input) -> Result<'_> {
+    alt((
+        recognize(tuple((tag("<!--"), take_until("-->"), tag("-->")))),
+        recognize(tuple((tag("<![CDATA["), take_until("]]>"), tag("]]>")))),
+        recognize(tuple((tag("<?"), take_until("?>"), tag("?>")))),
+        recognize(tuple((
+            tag("<!"),
+            take_till(|c| c == '>' || c == '['),
+            // the internal subset of a doctype can contain markup
+            opt(tuple((char('['), take_until("]"), char(']')))),
+            take_till(|c| c == '>'),
+            char('>'),
+        ))),
+        recognize(tuple((tag("</"), take_till(|c| c == '>'), char('>')))),
+    ))(input)
+}
+
+/// The name and attributes of a start tag
+fn start_tag(input: &Input) -> Result<'_, (&Input, Attributes<'_>)> {
+    let (input, name) = preceded(char('<'), xml_name)(input)?;
+    let (input, attributes) =
+        many0(attribute_parts.map(|(_, name, _, value, _)| (name, value)))(input)?;
+    let (input, _) = tuple((multispace0, opt(char('/')), char('>')))(input)?;
+    Ok((input, (name, attributes)))
+}
+
+/// The name and attributes of the first start tag, after the prolog, comments and text
+fn root_tag(input: &Input) -> Result<'_, (&Input, Attributes<'_>)> {
+    let (input, _) = many0(alt((markup, take_till1(|c| c == '<'))))(input)?;
+    start_tag(input)
+}
+
+/// The attributes of the root `<svg>` element, by name. Empty if the SVG doesn't start with
+/// an `<svg>` element.
+pub fn root_attributes(input: &Input) -> Attributes<'_> {
+    match root_tag(input) {
+        Ok((_, (name, attributes))) if name.rsplit(':').next() == Some("svg") => attributes,
+        _ => Vec::new(),
+    }
+}
+
+/// Every start tag in the SVG, as its byte range in the input and its attributes by name.
+/// Comments, CDATA sections and the like are skipped, so markup inside them isn't a tag.
+pub fn start_tags(input: &Input) -> Vec<(Range<usize>, Attributes<'_>)> {
+    let mut result = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let start = input.len() - rest.len();
+        if let Ok((next, (_, attributes))) = start_tag(rest) {
+            result.push((start..input.len() - next.len(), attributes));
+            rest = next;
+            continue;
+        }
+        // a stray '<' is skipped like text
+        rest = match alt((markup, take_till1(|c| c == '<')))(rest) {
+            Ok((next, _)) => next,
+            Err(_) => &rest[1..],
+        };
+    }
+    result
+}
+
+/// Split an SVG into colors in paint properties and text. Joining the text of all elements
+/// gives back the input.
+pub fn paint_text(
+    input: &Input,
+    parse_opacity: bool,
+) -> std::result::Result<Vec<TextElement<'_>>, nom::error::Error<&Input>> {
+    all_consuming(fold_many0(
+        alt((
+            markup.map(text),
+            |input| element(input, parse_opacity),
+            take_till1(|c| c == '<').map(text),
+            // a stray '<', keep going rather than failing on malformed markup
+            take(1usize).map(text),
+        )),
+        Vec::new,
+        extend,
+    ))(input)
+    .finish()
+    .map(|(_rest, vec)| vec)
+}
+
+/// Replace the colors that `parser::xml_text` found in paint properties with the colors and
+/// contexts that `paint_text` finds, so that both modes fold opacity attributes and element
+/// opacity into the alpha, and add opacity attributes after colors, the same way. Other
+/// colors are kept as they are.
+pub fn with_paint_contexts<'a>(
+    input: &'a Input,
+    elements: Vec<TextElement<'a>>,
+    parse_opacity: bool,
+) -> Vec<TextElement<'a>> {
+    let Ok(paint_elements) = paint_text(input, parse_opacity) else {
+        return elements;
+    };
+    // the same color text in both modes is the same slice of the input
+    let key = |text: &Input| (text.as_ptr(), text.len());
+    let mut paint_colors: HashMap<_, _> = paint_elements
+        .into_iter()
+        .filter_map(|element| match element {
+            TextElement::Color(color, text, context) => Some((key(text), (color, context))),
+            TextElement::Text(_) => None,
+        })
+        .collect();
+
+    elements
+        .into_iter()
+        .map(|element| match element {
+            TextElement::Color(_, text, _) => match paint_colors.remove(&key(text)) {
+                Some((color, context)) => TextElement::Color(color, text, context),
+                None => element,
+            },
+            TextElement::Text(_) => element,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Color;
+
+    use super::*;
+
+    fn colors(input: &str) -> Vec<(Color, &str)> {
+        let elements = paint_text(input, true).unwrap();
+        let joined: String = elements
+            .iter()
+            .map(|element| match element {
+                TextElement::Text(text) | TextElement::Color(_, text, _) => *text,
+            })
+            .collect();
+        assert_eq!(joined, input);
+
+        elements
+            .into_iter()
+            .filter_map(|element| match element {
+                TextElement::Color(color, text, _) => Some((color, text)),
+                TextElement::Text(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            colors(r##"<rect id="abc123" fill="#abc123" stroke='red' data-x="#fff"/>"##),
+            vec![
+                (Color::RGB(0xab, 0xc1, 0x23), "#abc123"),
+                (Color::RGB(255, 0, 0), "red"),
+            ]
+        );
+        assert_eq!(
+            colors(r##"<use href="#ff0000" fill="url(#ff0000) #00f"/>"##),
+            vec![(Color::RGB(0, 0, 255), "#00f")]
+        );
+    }
+
+    #[test]
+    fn test_css() {
+        assert_eq!(
+            colors(r##"<path style="fill:#ff0000;fill-opacity:0.5;outline-color:#fff"/>"##),
+            vec![(Color::RGBA(255, 0, 0, 128), "#ff0000;fill-opacity:0.5")]
+        );
+        assert_eq!(
+            colors(
+                r##"<style><![CDATA[
+                    #a0a0a0 { stroke : blue } /* fill: #fff */ .x { stop-color: rgb(1 2 3) }
+                ]]></style>"##
+            ),
+            vec![
+                (Color::RGB(0, 0, 255), "blue"),
+                (Color::RGB(1, 2, 3), "rgb(1 2 3)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_contexts() {
+        let contexts = |input| -> Vec<_> {
+            paint_text(input, true)
+                .unwrap()
+                .into_iter()
+                .filter_map(|element| match element {
+                    TextElement::Color(_, _, context) => Some(context.form),
+                    TextElement::Text(_) => None,
+                })
+                .collect()
+        };
+        let attribute = |add_opacity| PropertyForm::Attribute {
+            quote: '"',
+            add_opacity,
+        };
+        assert_eq!(
+            contexts(
+                r##"<path fill="#fff " stroke="#000" stroke-opacity="0.5" style="fill:red"/>"##
+            ),
+            vec![attribute(true), attribute(false), PropertyForm::Declaration]
+        );
+        assert_eq!(
+            contexts(r##"<path fill="#fff url(#a)"/>"##),
+            vec![attribute(false)]
+        );
+    }
+
+    #[test]
+    fn test_opacities() {
+        assert_eq!(
+            colors(
+                r##"<path fill="#f00" stroke="#00f" stroke-opacity="50%" opacity=".5" style="fill-opacity:0.5"/>"##
+            ),
+            vec![
+                (Color::RGBA(255, 0, 0, 64), "#f00"),
+                (Color::RGBA(0, 0, 255, 64), "#00f"),
+            ]
+        );
+        assert_eq!(
+            colors(
+                r##"<style>.a { stroke: #000; fill: #fff; stroke-opacity: 0.5 } .b { stroke: #000 }</style>"##
+            ),
+            vec![
+                (Color::RGBA(0, 0, 0, 128), "#000"),
+                (Color::RGB(255, 255, 255), "#fff"),
+                (Color::RGB(0, 0, 0), "#000"),
+            ]
+        );
+        assert_eq!(
+            colors(r##"<stop style="stop-color:#000;stop-opacity:0.5" stop-opacity="1"/>"##),
+            vec![(Color::RGBA(0, 0, 0, 128), "#000;stop-opacity:0.5")]
+        );
+    }
+
+    #[test]
+    fn test_ignored() {
+        assert_eq!(
+            colors(
+                r##"<?xml version="1.0"?><!-- fill="#fff" --><text>fill: #fff <tspan>#000</tspan></text>"##
+            ),
+            vec![]
+        );
+    }
+}
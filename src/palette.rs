@@ -0,0 +1,139 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{cli::ColorMapping, parser::Color};
+
+#[derive(Error, Debug)]
+pub enum PaletteError {
+    #[error("failed to read {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("failed to parse {0}: {1}")]
+    Toml(PathBuf, toml::de::Error),
+    #[error("failed to parse {0}: {1}")]
+    Json(PathBuf, serde_json::Error),
+    #[error("{0}: invalid color {1:?}: {2}")]
+    InvalidColor(PathBuf, String, String),
+    #[error("{0}: {1:?} is neither a palette color nor a valid color")]
+    UnknownColor(PathBuf, String),
+}
+
+/// Parse a flat table of strings as TOML, or as JSON if the extension of `path` is `.json`.
+/// `path` is also used in error messages.
+fn parse_table(text: &str, path: &Path) -> Result<BTreeMap<String, String>, PaletteError> {
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    if is_json {
+        serde_json::from_str(text).map_err(|err| PaletteError::Json(path.to_owned(), err))
+    } else {
+        toml::from_str(text).map_err(|err| PaletteError::Toml(path.to_owned(), err))
+    }
+}
+
+/// Read a flat table of strings from a TOML file, or a JSON file if the extension is `.json`
+fn read_table(path: &Path) -> Result<BTreeMap<String, String>, PaletteError> {
+    let text = fs::read_to_string(path).map_err(|err| PaletteError::Read(path.to_owned(), err))?;
+    parse_table(&text, path)
+}
+
+/// Named colors, e.g. `accent = "#6699cc"`. Mappings refer to colors by name, so a theme
+/// can be re-skinned by swapping the palette file.
+#[derive(Debug, Default)]
+pub struct Palette {
+    colors: BTreeMap<String, Color>,
+}
+
+impl Palette {
+    pub fn load(path: &Path) -> Result<Self, PaletteError> {
+        let text =
+            fs::read_to_string(path).map_err(|err| PaletteError::Read(path.to_owned(), err))?;
+        Self::parse(&text, path)
+    }
+
+    /// Parse the text of a palette file, see `parse_table`
+    pub fn parse(text: &str, path: &Path) -> Result<Self, PaletteError> {
+        let colors = parse_table(text, path)?
+            .into_iter()
+            .map(|(name, value)| match value.parse() {
+                Ok(color) => Ok((name, color)),
+                Err(err) => Err(PaletteError::InvalidColor(path.to_owned(), value, err)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { colors })
+    }
+
+    /// Look up a palette color by name, falling back to parsing the text as a color
+    pub fn resolve(&self, text: &str) -> Option<Color> {
+        self.colors.get(text).cloned().or_else(|| text.parse().ok())
+    }
+
+    /// Whether a color is one of the palette's colors
+    pub fn contains(&self, color: &Color) -> bool {
+        self.colors.values().any(|other| other == color)
+    }
+
+    /// Parse a table mapping source colors to palette names or colors. `source` is only
+    /// used in error messages.
+    pub fn resolve_mappings(
+        &self,
+        mappings: &BTreeMap<String, String>,
+        source: &Path,
+    ) -> Result<Vec<ColorMapping>, PaletteError> {
+        mappings
+            .iter()
+            .map(|(old, new)| {
+                let old = old.parse().map_err(|err| {
+                    PaletteError::InvalidColor(source.to_owned(), old.clone(), err)
+                })?;
+                let new = self
+                    .resolve(new)
+                    .ok_or_else(|| PaletteError::UnknownColor(source.to_owned(), new.clone()))?;
+                Ok(ColorMapping { map: (), old, new })
+            })
+            .collect()
+    }
+
+    /// Load a mapping file, e.g. `"#ff0000" = "accent"`
+    pub fn load_mappings(&self, path: &Path) -> Result<Vec<ColorMapping>, PaletteError> {
+        self.resolve_mappings(&read_table(path)?, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_mappings() {
+        let palette = Palette {
+            colors: BTreeMap::from([("accent".into(), Color::RGB(0x66, 0x99, 0xcc))]),
+        };
+        let mappings = BTreeMap::from([
+            ("#ff0000".into(), "accent".into()),
+            ("#00ff00".into(), "#123".into()),
+        ]);
+
+        let result = palette
+            .resolve_mappings(&mappings, Path::new("map.toml"))
+            .unwrap();
+        let result: Vec<_> = result.into_iter().map(|cm| (cm.old, cm.new)).collect();
+        assert_eq!(
+            result,
+            vec![
+                (Color::RGB(0, 255, 0), Color::RGB(0x11, 0x22, 0x33)),
+                (Color::RGB(255, 0, 0), Color::RGB(0x66, 0x99, 0xcc)),
+            ]
+        );
+
+        let unknown = BTreeMap::from([("#ff0000".into(), "highlight".into())]);
+        assert!(matches!(
+            palette.resolve_mappings(&unknown, Path::new("map.toml")),
+            Err(PaletteError::UnknownColor(..))
+        ));
+    }
+}
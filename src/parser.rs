@@ -1,31 +1,36 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take},
-    character::complete::{char, none_of, one_of, space0, u8},
-    combinator::{all_consuming, cut, eof, not, opt, peek, recognize},
-    multi::{many0, many1},
+    bytes::complete::{tag, tag_no_case, take, take_while1},
+    character::complete::{char, none_of, one_of, space0, space1},
+    combinator::{all_consuming, consumed, eof, map_opt, not, opt, peek, recognize},
+    error::ErrorKind,
+    multi::{fold_many0, many1},
     number::complete::float,
-    sequence::{delimited, preceded, tuple},
+    sequence::{delimited, preceded, terminated, tuple},
     Finish, IResult, Parser,
 };
 
-type Input = str;
+use crate::{hsl::hsl_to_rgb, named_colors::NAMED_COLORS};
 
-type Result<'a, O = &'a Input> = IResult<&'a Input, O>;
+pub(crate) type Input = str;
 
+pub(crate) type Result<'a, O = &'a Input> = IResult<&'a Input, O>;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Eq, Debug, Hash, Clone)]
 pub enum Color {
     RGB(u8, u8, u8),
     RGBA(u8, u8, u8, u8),
 }
 
-impl ToString for Color {
-    fn to_string(&self) -> String {
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Color::RGB(r, g, b) => format!("rgb({}, {}, {})", r, g, b),
-            Color::RGBA(r, g, b, a) => format!(
+            Color::RGB(r, g, b) => write!(f, "rgb({}, {}, {})", r, g, b),
+            Color::RGBA(r, g, b, a) => write!(
+                f,
                 "rgb({}, {}, {});fill-opacity:{}",
                 r,
                 g,
@@ -39,29 +44,29 @@ impl ToString for Color {
 impl Color {
     pub fn r(&self) -> u8 {
         match self {
-            Color::RGB(r, g, b) => *r,
-            Color::RGBA(r, g, b, a) => *r,
+            Color::RGB(r, _, _) => *r,
+            Color::RGBA(r, _, _, _) => *r,
         }
     }
 
     pub fn g(&self) -> u8 {
         match self {
-            Color::RGB(r, g, b) => *g,
-            Color::RGBA(r, g, b, a) => *g,
+            Color::RGB(_, g, _) => *g,
+            Color::RGBA(_, g, _, _) => *g,
         }
     }
 
     pub fn b(&self) -> u8 {
         match self {
-            Color::RGB(r, g, b) => *b,
-            Color::RGBA(r, g, b, a) => *b,
+            Color::RGB(_, _, b) => *b,
+            Color::RGBA(_, _, b, _) => *b,
         }
     }
 
     pub fn a(&self) -> Option<u8> {
         match self {
-            Color::RGB(r, g, b) => None,
-            Color::RGBA(r, g, b, a) => Some(*a),
+            Color::RGB(_, _, _) => None,
+            Color::RGBA(_, _, _, a) => Some(*a),
         }
     }
 
@@ -72,8 +77,10 @@ impl Color {
         }
     }
 
+    /// Multiply the alpha by an opacity, e.g. from a `fill-opacity` property
     pub fn with_opacity(&self, opacity: f32) -> Self {
-        self.with_a((255.0 * opacity).round() as u8)
+        let a = self.a().unwrap_or(255) as f32;
+        Self::RGBA(self.r(), self.g(), self.b(), (a * opacity).round() as u8)
     }
 
     pub fn is_reaper_reserved(&self) -> bool {
@@ -94,56 +101,186 @@ impl FromStr for Color {
     type Err = String;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        all_consuming(color)(&s)
+        all_consuming(color)(s)
             .finish()
             .map(|(_, o)| o)
             .map_err(|x| x.to_string())
     }
 }
 
-fn color_rgb_value(input: &Input) -> Result<u8> {
-    delimited(space0, u8, space0)(input)
+/// Unit after a number in a color function
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Unit {
+    None,
+    Percent,
+    Deg,
+    Rad,
+    Grad,
+    Turn,
 }
 
-fn rgb_numeric(input: &Input) -> Result<Color> {
-    delimited(
-        tag("rgb("),
-        cut(tuple((
-            color_rgb_value,
-            char(','),
-            color_rgb_value,
-            char(','),
-            color_rgb_value,
-        ))),
-        cut(char(')')),
-    )
-    .map(|(r, _, g, _, b)| Color::RGB(r, g, b))
+fn unit(input: &Input) -> Result<'_, Unit> {
+    opt(alt((
+        tag("%").map(|_| Unit::Percent),
+        tag_no_case("deg").map(|_| Unit::Deg),
+        tag_no_case("rad").map(|_| Unit::Rad),
+        tag_no_case("grad").map(|_| Unit::Grad),
+        tag_no_case("turn").map(|_| Unit::Turn),
+    )))
+    .map(|unit| unit.unwrap_or(Unit::None))
     .parse(input)
 }
 
-fn rgba_numeric(input: &Input) -> Result<Color> {
+/// A number with an optional unit, e.g. `255`, `50%` or `120deg`
+fn component(input: &Input) -> Result<'_, (f32, Unit)> {
+    tuple((float, unit))(input)
+}
+
+/// Components are separated by commas in the legacy syntax, and by spaces in CSS Color 4
+fn separator(input: &Input) -> Result<'_> {
+    alt((delimited(space0, tag(","), space0), space1))(input)
+}
+
+/// The alpha is separated by a comma in the legacy syntax, and by a slash in CSS Color 4
+fn alpha_separator(input: &Input) -> Result<'_> {
+    delimited(space0, alt((tag(","), tag("/"))), space0)(input)
+}
+
+type Components = ((f32, Unit), (f32, Unit), (f32, Unit), Option<(f32, Unit)>);
+
+fn function_args(input: &Input) -> Result<'_, Components> {
     delimited(
-        tag("rgba("),
-        cut(tuple((
-            color_rgb_value,
-            char(','),
-            color_rgb_value,
-            char(','),
-            color_rgb_value,
-            char(','),
-            color_rgb_value,
-        ))),
-        cut(char(')')),
-    )
-    .map(|(r, _, g, _, b, _, a)| Color::RGBA(r, g, b, a))
-    .parse(input)
+        tuple((char('('), space0)),
+        tuple((
+            component,
+            preceded(separator, component),
+            preceded(separator, component),
+            opt(preceded(alpha_separator, component)),
+        )),
+        tuple((space0, char(')'))),
+    )(input)
+}
+
+/// RGB channel from 0 to 255, or from 0% to 100%
+fn rgb_channel((value, unit): (f32, Unit)) -> Option<u8> {
+    let value = match unit {
+        Unit::None => value,
+        Unit::Percent if (0.0..=100.0).contains(&value) => value * 2.55,
+        _ => return None,
+    };
+    (0.0..=255.0).contains(&value).then(|| value.round() as u8)
+}
+
+/// Alpha from 0 to 1, or from 0% to 100%, as in CSS. Values above 1 are rejected rather
+/// than read as 0 to 255, which older versions of this tool accepted for `rgba()`; see
+/// `color_function`. Only an old alpha of 1 changes meaning, from almost transparent to
+/// opaque, as it is valid either way.
+fn alpha_channel((value, unit): (f32, Unit)) -> Option<u8> {
+    match unit {
+        Unit::Percent if (0.0..=100.0).contains(&value) => Some((value * 2.55).round() as u8),
+        Unit::None if (0.0..=1.0).contains(&value) => Some((value * 255.0).round() as u8),
+        _ => None,
+    }
+}
+
+/// Hue in degrees
+fn hue((value, unit): (f32, Unit)) -> Option<f32> {
+    match unit {
+        Unit::None | Unit::Deg => Some(value),
+        Unit::Rad => Some(value.to_degrees()),
+        Unit::Grad => Some(value * 0.9),
+        Unit::Turn => Some(value * 360.0),
+        Unit::Percent => None,
+    }
+}
+
+/// Saturation or lightness from 0% to 100%, returned as a fraction
+fn hsl_percent((value, unit): (f32, Unit)) -> Option<f32> {
+    match unit {
+        Unit::None | Unit::Percent if (0.0..=100.0).contains(&value) => Some(value / 100.0),
+        _ => None,
+    }
 }
 
-fn color_numeric(input: &Input) -> Result<Color> {
-    alt((rgb_numeric, rgba_numeric))(input)
+fn with_alpha(r: u8, g: u8, b: u8, alpha: Option<(f32, Unit)>) -> Option<Color> {
+    Some(match alpha {
+        Some(alpha) => Color::RGBA(r, g, b, alpha_channel(alpha)?),
+        None => Color::RGB(r, g, b),
+    })
 }
 
-fn color_hex(input: &Input) -> Result<Color> {
+/// A color function such as `rgb()`, given its two names and how to convert its arguments.
+/// An alpha out of range is an error rather than making the text not a color, so that the
+/// 0 to 255 alphas that older versions of this tool accepted are noticed.
+fn color_function<'a>(
+    names: (&'static str, &'static str),
+    to_color: impl Fn(Components) -> Option<Color>,
+) -> impl FnMut(&'a Input) -> Result<'a, Color> {
+    move |input| {
+        let (rest, args) = preceded(
+            tuple((
+                alt((tag_no_case(names.0), tag_no_case(names.1))),
+                peek(char('(')),
+            )),
+            function_args,
+        )(input)?;
+        let error = |kind| nom::error::Error::new(input, kind);
+        if args.3.is_some_and(|alpha| alpha_channel(alpha).is_none()) {
+            return Err(nom::Err::Failure(error(ErrorKind::Verify)));
+        }
+        match to_color(args) {
+            Some(color) => Ok((rest, color)),
+            None => Err(nom::Err::Error(error(ErrorKind::MapOpt))),
+        }
+    }
+}
+
+/// `rgb()` or `rgba()`, in either the legacy comma syntax or the CSS Color 4 space syntax
+fn rgb_numeric(input: &Input) -> Result<'_, Color> {
+    color_function(("rgba", "rgb"), |(r, g, b, alpha)| {
+        with_alpha(rgb_channel(r)?, rgb_channel(g)?, rgb_channel(b)?, alpha)
+    })(input)
+}
+
+/// `hsl()` or `hsla()`, in either the legacy comma syntax or the CSS Color 4 space syntax
+fn hsl_numeric(input: &Input) -> Result<'_, Color> {
+    color_function(("hsla", "hsl"), |(h, s, l, alpha)| {
+        let (r, g, b) = hsl_to_rgb(hue(h)?, hsl_percent(s)?, hsl_percent(l)?);
+        with_alpha(r, g, b, alpha)
+    })(input)
+}
+
+fn color_numeric(input: &Input) -> Result<'_, Color> {
+    alt((rgb_numeric, hsl_numeric))(input)
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// A run of identifier characters, e.g. an attribute name or a word of text
+pub(crate) fn identifier(input: &Input) -> Result<'_> {
+    take_while1(is_identifier_char)(input)
+}
+
+/// A CSS named color such as `red`, or `transparent`
+fn named_color(input: &Input) -> Result<'_, Color> {
+    map_opt(identifier, |name| {
+        let name = name.to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Color::RGBA(0, 0, 0, 0));
+        }
+        NAMED_COLORS
+            .binary_search_by_key(&name.as_str(), |(name, _)| name)
+            .ok()
+            .map(|i| {
+                let [r, g, b] = NAMED_COLORS[i].1;
+                Color::RGB(r, g, b)
+            })
+    })(input)
+}
+
+fn color_hex(input: &Input) -> Result<'_, Color> {
     delimited(
         alt((tag("#"), tag("0x"))),
         tuple((
@@ -171,15 +308,15 @@ fn color_hex(input: &Input) -> Result<Color> {
     )
     .map(|(r, g, b, a)| {
         let r =
-            u8::from_str_radix(r, 16).expect(format!("failed to convert {r} to number").as_str());
+            u8::from_str_radix(r, 16).unwrap_or_else(|_| panic!("failed to convert {r} to number"));
         let g =
-            u8::from_str_radix(g, 16).expect(format!("failed to convert {g} to number").as_str());
+            u8::from_str_radix(g, 16).unwrap_or_else(|_| panic!("failed to convert {g} to number"));
         let b =
-            u8::from_str_radix(b, 16).expect(format!("failed to convert {b} to number").as_str());
+            u8::from_str_radix(b, 16).unwrap_or_else(|_| panic!("failed to convert {b} to number"));
         match a {
             Some(a) => {
                 let a = u8::from_str_radix(a, 16)
-                    .expect(format!("failed to convert {a} to number").as_str());
+                    .unwrap_or_else(|_| panic!("failed to convert {a} to number"));
                 Color::RGBA(r, g, b, a)
             }
             None => Color::RGB(r, g, b),
@@ -188,88 +325,233 @@ fn color_hex(input: &Input) -> Result<Color> {
     .parse(input)
 }
 
-fn rgb_hex_short(input: &Input) -> Result<Color> {
-    delimited(
-        alt((tag("#"), tag("0x"))),
+fn rgb_hex_short(input: &Input) -> Result<'_, Color> {
+    // only CSS `#rgba` has an alpha digit, `0x` colors are always `0xrgb`
+    let alpha = |prefix| {
+        move |input| match prefix {
+            "#" => opt(one_of("0123456789abcdefABCDEF"))(input),
+            _ => Ok((input, None)),
+        }
+    };
+    let (input, prefix) = alt((tag("#"), tag("0x")))(input)?;
+    terminated(
         tuple((
-            recognize(one_of("0123456789abcdefABCDEF")),
-            recognize(one_of("0123456789abcdefABCDEF")),
-            recognize(one_of("0123456789abcdefABCDEF")),
+            one_of("0123456789abcdefABCDEF"),
+            one_of("0123456789abcdefABCDEF"),
+            one_of("0123456789abcdefABCDEF"),
+            alpha(prefix),
         )),
         peek(alt((
             eof.map(|_| ()),
             none_of("0123456789abcdefABCDEF").map(|_| ()),
         ))),
     )
-    .map(|(r, g, b)| {
-        let r = u8::from_str_radix(r, 16)
-            .expect(format!("failed to convert {r} to number").as_str())
-            * 0x11;
-        let g = u8::from_str_radix(g, 16)
-            .expect(format!("failed to convert {g} to number").as_str())
-            * 0x11;
-        let b = u8::from_str_radix(b, 16)
-            .expect(format!("failed to convert {b} to number").as_str())
-            * 0x11;
-        Color::RGB(r, g, b)
+    .map(|(r, g, b, a)| {
+        // each digit is repeated, e.g. #f80 is #ff8800
+        let digit = |x: char| x.to_digit(16).unwrap() as u8 * 0x11;
+        match a {
+            Some(a) => Color::RGBA(digit(r), digit(g), digit(b), digit(a)),
+            None => Color::RGB(digit(r), digit(g), digit(b)),
+        }
     })
     .parse(input)
 }
 
-fn color(input: &Input) -> Result<Color> {
-    alt((color_hex, rgb_hex_short, color_numeric))(input)
+pub(crate) fn color(input: &Input) -> Result<'_, Color> {
+    alt((color_hex, rgb_hex_short, color_numeric, named_color))(input)
+}
+
+/// A property whose value is a color
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ColorProperty {
+    Fill,
+    Stroke,
+    StopColor,
+    FloodColor,
+    LightingColor,
+    Color,
+}
+
+impl ColorProperty {
+    /// The property that sets the opacity of this color, if there is one
+    pub fn opacity_property(self) -> Option<&'static str> {
+        match self {
+            ColorProperty::Fill => Some("fill-opacity"),
+            ColorProperty::Stroke => Some("stroke-opacity"),
+            ColorProperty::StopColor => Some("stop-opacity"),
+            ColorProperty::FloodColor => Some("flood-opacity"),
+            ColorProperty::LightingColor | ColorProperty::Color => None,
+        }
+    }
+}
+
+/// How the property containing a color is written
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum PropertyForm {
+    #[default]
+    Unknown,
+    /// An attribute value. `add_opacity` is set when an opacity attribute can be inserted
+    /// right after the color, i.e. the color ends the value and the element doesn't have
+    /// the opacity attribute yet.
+    Attribute { quote: char, add_opacity: bool },
+    /// A CSS declaration, in a `style` attribute or a `<style>` element
+    Declaration,
+}
+
+/// Where a color was found in the SVG, so that a replacement can be written the same way
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ColorContext {
+    pub property: Option<ColorProperty>,
+    pub form: PropertyForm,
+    /// Alpha from opacity properties that aren't part of the color's text, such as an
+    /// element's `opacity`. It's already included in the color's alpha.
+    pub opacity: Option<u8>,
 }
 
 #[derive(PartialEq, Debug)]
 pub enum TextElement<'a> {
     Text(&'a Input),
-    Color(Color),
+    /// A color, along with the text it was parsed from
+    Color(Color, &'a Input, ColorContext),
 }
 
-fn fill_opacity(input: &Input) -> Result<f32> {
-    preceded(tag(";fill-opacity:"), float)(input)
+/// An opacity, either a number or a percentage, clamped to 0..=1
+pub(crate) fn opacity(input: &Input) -> Result<'_, f32> {
+    tuple((float, opt(char('%'))))
+        .map(|(value, percent)| match percent {
+            Some(_) => value / 100.0,
+            None => value,
+        })
+        .map(|value| value.clamp(0.0, 1.0))
+        .parse(input)
 }
 
-fn color_with_fill_opacity(input: &Input) -> Result<Color> {
-    color(input).map(|(input, color)| match fill_opacity(input) {
-        Ok((input, opacity)) => (input, color.with_opacity(opacity)),
-        Err(_) => (input, color),
-    })
+/// An opacity declaration right after a color, e.g. `;stroke-opacity:0.5`
+fn opacity_suffix<'a>(name: &'static str) -> impl FnMut(&'a Input) -> Result<'a, f32> {
+    preceded(
+        tuple((char(';'), space0, tag(name), space0, char(':'), space0)),
+        opacity,
+    )
 }
 
-fn non_color_text(input: &Input) -> Result {
-    recognize(many1(preceded(not(color_with_fill_opacity), take(1usize))))(input)
+/// Parse a color with a color parser, then the declaration of its opacity property if
+/// `parse_opacity` is set, e.g. `;stroke-opacity:0.5` for strokes. Colors outside of a
+/// property use `;fill-opacity:`.
+pub(crate) fn with_opacity<'a>(
+    mut color: impl FnMut(&'a Input) -> Result<'a, Color>,
+    property: Option<ColorProperty>,
+    parse_opacity: bool,
+) -> impl FnMut(&'a Input) -> Result<'a, Color> {
+    let name = match property {
+        Some(property) => property.opacity_property(),
+        None => Some("fill-opacity"),
+    };
+    move |input| {
+        let (input, color) = color(input)?;
+        match name {
+            Some(name) if parse_opacity => match opacity_suffix(name)(input) {
+                Ok((input, opacity)) => Ok((input, color.with_opacity(opacity))),
+                Err(_) => Ok((input, color)),
+            },
+            _ => Ok((input, color)),
+        }
+    }
 }
 
-fn text_with_colors_with_opacity(input: &Input) -> Result<Vec<TextElement>> {
-    many0(alt((
-        color_with_fill_opacity.map(|x| TextElement::Color(x)),
-        non_color_text.map(|x| TextElement::Text(x)),
-    )))
-    .parse(input)
+/// Colors that are recognized anywhere in the text
+fn text_color(input: &Input) -> Result<'_, Color> {
+    alt((color_hex, rgb_hex_short, color_numeric))(input)
+}
+
+/// Name of a property whose value is a color
+pub(crate) fn color_property_name(input: &Input) -> Result<'_, ColorProperty> {
+    alt((
+        tag("fill").map(|_| ColorProperty::Fill),
+        tag("stroke").map(|_| ColorProperty::Stroke),
+        tag("stop-color").map(|_| ColorProperty::StopColor),
+        tag("flood-color").map(|_| ColorProperty::FloodColor),
+        tag("lighting-color").map(|_| ColorProperty::LightingColor),
+        tag("color").map(|_| ColorProperty::Color),
+    ))(input)
 }
 
-fn text_with_colors(input: &Input) -> Result<Vec<TextElement>> {
-    many0(alt((
-        color.map(|x| TextElement::Color(x)),
-        non_color_text.map(|x| TextElement::Text(x)),
+/// The start of a color property's value, e.g. `fill="` or `stroke: `
+fn color_property(input: &Input) -> Result<'_, (&Input, ColorContext)> {
+    consumed(tuple((
+        color_property_name,
+        alt((
+            preceded(char('='), one_of("\"'")).map(|quote| PropertyForm::Attribute {
+                quote,
+                add_opacity: false,
+            }),
+            tuple((space0, char(':'), space0)).map(|_| PropertyForm::Declaration),
+        )),
     )))
+    .map(|(text, (property, form))| {
+        let context = ColorContext {
+            property: Some(property),
+            form,
+            opacity: None,
+        };
+        (text, context)
+    })
     .parse(input)
 }
 
+/// A color as the value of a color property, e.g. `fill="red"`. Named colors aren't
+/// recognized anywhere else, since they would match ids, labels and ordinary text.
+fn color_value(
+    input: &Input,
+    parse_opacity: bool,
+) -> Result<'_, (&Input, (&Input, Color), ColorContext)> {
+    let (input, (property, context)) = color_property(input)?;
+    let (input, color) = consumed(with_opacity(color, context.property, parse_opacity))(input)?;
+    Ok((input, (property, color, context)))
+}
+
+/// Text up to the next color. Words are consumed whole, so that color properties are only
+/// found at word boundaries.
+fn non_color_text(input: &Input) -> Result<'_> {
+    recognize(many1(preceded(
+        not(alt((
+            text_color.map(|_| ()),
+            (|input| color_value(input, false)).map(|_| ()),
+        ))),
+        alt((identifier, take(1usize))),
+    )))(input)
+}
+
+fn text_with_colors(input: &Input, parse_opacity: bool) -> Result<'_, Vec<TextElement<'_>>> {
+    fold_many0(
+        alt((
+            (|input| color_value(input, parse_opacity)).map(
+                |(property, (text, color), context)| {
+                    vec![
+                        TextElement::Text(property),
+                        TextElement::Color(color, text, context),
+                    ]
+                },
+            ),
+            consumed(with_opacity(text_color, None, parse_opacity)).map(|(text, color)| {
+                vec![TextElement::Color(color, text, ColorContext::default())]
+            }),
+            non_color_text.map(|text| vec![TextElement::Text(text)]),
+        )),
+        Vec::new,
+        |mut result, elements| {
+            result.extend(elements);
+            result
+        },
+    )(input)
+}
+
 pub fn xml_text(
     input: &Input,
     parse_opacity: bool,
-) -> std::result::Result<Vec<TextElement>, nom::error::Error<&Input>> {
-    if parse_opacity {
-        all_consuming(text_with_colors_with_opacity)(input)
-            .finish()
-            .map(|(_rest, vec)| vec)
-    } else {
-        all_consuming(text_with_colors)(input)
-            .finish()
-            .map(|(_rest, vec)| vec)
-    }
+) -> std::result::Result<Vec<TextElement<'_>>, nom::error::Error<&Input>> {
+    all_consuming(|input| text_with_colors(input, parse_opacity))(input)
+        .finish()
+        .map(|(_rest, vec)| vec)
 }
 
 #[cfg(test)]
@@ -297,6 +579,43 @@ mod tests {
         assert!(rgb_numeric("rgb(-1, 0, 0)").is_err());
     }
 
+    #[test]
+    fn test_color_css() {
+        let parse = |text: &str| text.parse::<Color>().unwrap();
+
+        // alpha as a fraction or percentage, legacy 0-255 integers are rejected
+        assert_eq!(parse("rgba(1, 2, 3, 0)"), Color::RGBA(1, 2, 3, 0));
+        assert_eq!(parse("rgba(1, 2, 3, 1)"), Color::RGBA(1, 2, 3, 255));
+        assert_eq!(parse("rgba(1, 2, 3, 0.5)"), Color::RGBA(1, 2, 3, 128));
+        assert_eq!(parse("rgba(1, 2, 3, 25%)"), Color::RGBA(1, 2, 3, 64));
+        assert!("rgba(1, 2, 3, 2)".parse::<Color>().is_err());
+        assert!("rgba(1, 2, 3, 255)".parse::<Color>().is_err());
+        // in an SVG, old 0 to 255 alphas are an error rather than text
+        assert!(xml_text(r#"<a fill="rgba(1, 2, 3, 255)"/>"#, false).is_err());
+        assert!(xml_text(r#"<a fill="hsla(0, 0%, 0%, 150%)"/>"#, false).is_err());
+        assert_eq!(parse("rgb(100%, 0%, 50%)"), Color::RGB(255, 0, 128));
+
+        // CSS Color 4 space syntax
+        assert_eq!(parse("rgb(1 2 3)"), Color::RGB(1, 2, 3));
+        assert_eq!(parse("rgb(1 2 3 / 50%)"), Color::RGBA(1, 2, 3, 128));
+
+        assert_eq!(parse("hsl(120, 100%, 50%)"), Color::RGB(0, 255, 0));
+        assert_eq!(
+            parse("hsla(240deg 100% 50% / 0.5)"),
+            Color::RGBA(0, 0, 255, 128)
+        );
+        assert_eq!(parse("hsl(0.5turn, 100%, 50%)"), Color::RGB(0, 255, 255));
+
+        assert_eq!(parse("#f80c"), Color::RGBA(0xff, 0x88, 0x00, 0xcc));
+        assert_eq!(parse("rebeccapurple"), Color::RGB(0x66, 0x33, 0x99));
+        assert_eq!(parse("Red"), Color::RGB(255, 0, 0));
+        assert_eq!(parse("transparent"), Color::RGBA(0, 0, 0, 0));
+
+        assert!("hsl(0, 120%, 50%)".parse::<Color>().is_err());
+        assert!("rgba(1, 2, 3, 1.5)".parse::<Color>().is_err());
+        assert!("notacolor".parse::<Color>().is_err());
+    }
+
     #[test]
     fn test_color_hex() {
         assert_eq!(color_hex("#000000").unwrap().1, Color::RGB(0, 0, 0));
@@ -323,6 +642,12 @@ mod tests {
             rgb_hex_short("0x123").unwrap().1,
             Color::RGB(0x11, 0x22, 0x33)
         );
+        assert_eq!(
+            rgb_hex_short("#1234").unwrap().1,
+            Color::RGBA(0x11, 0x22, 0x33, 0x44)
+        );
+        assert!(rgb_hex_short("0x1234").is_err());
+        assert_eq!(non_color_text("0x1234").unwrap().1, "0x1234");
     }
 
     #[test]
@@ -331,33 +656,100 @@ mod tests {
         assert_eq!(non_color_text("apple 0x000000").unwrap().1, "apple ");
         assert_eq!(non_color_text("apple rgb(1,2,3)").unwrap().1, "apple ");
         assert!(non_color_text("rgb(1, 2, 3)").is_err());
+
+        // named colors only match as the value of a color property
+        assert_eq!(
+            non_color_text(r#"red id="red""#).unwrap().1,
+            r#"red id="red""#
+        );
+        assert_eq!(
+            non_color_text(r#"outline-fill="red""#).unwrap().1,
+            r#"outline-fill="red""#
+        );
+        assert_eq!(non_color_text("a {stroke: red}").unwrap().1, "a {");
+        assert_eq!(non_color_text("the rgb value").unwrap().1, "the rgb value");
+
+        // functions that aren't colors are passed through as text
+        let text = "<!-- hsl(foo) --><a fill=\"rgb(var(--x), 0, calc(1 + 2))\"/>";
+        assert_eq!(
+            xml_text(text, false).unwrap(),
+            vec![TextElement::Text(text)]
+        );
     }
 
     #[test]
     fn test_text() {
         assert_eq!(
-            text_with_colors_with_opacity("apple #000000").unwrap().1,
+            text_with_colors("apple #000000", true).unwrap().1,
             vec![
                 TextElement::Text("apple "),
-                TextElement::Color(Color::RGB(0, 0, 0))
+                TextElement::Color(Color::RGB(0, 0, 0), "#000000", ColorContext::default())
             ]
         );
         assert_eq!(
-            text_with_colors_with_opacity("apple 0x000000").unwrap().1,
+            text_with_colors("apple 0x000000", true).unwrap().1,
             vec![
                 TextElement::Text("apple "),
-                TextElement::Color(Color::RGB(0, 0, 0))
+                TextElement::Color(Color::RGB(0, 0, 0), "0x000000", ColorContext::default())
             ]
         );
         assert_eq!(
-            text_with_colors_with_opacity("apple rgb(1,2,3) apple")
-                .unwrap()
-                .1,
+            text_with_colors("apple rgb(1,2,3) apple", true).unwrap().1,
             vec![
                 TextElement::Text("apple "),
-                TextElement::Color(Color::RGB(1, 2, 3)),
+                TextElement::Color(Color::RGB(1, 2, 3), "rgb(1,2,3)", ColorContext::default()),
                 TextElement::Text(" apple")
             ]
         );
+        assert_eq!(
+            text_with_colors("<a fill=\"Red;fill-opacity:0.5\">", true)
+                .unwrap()
+                .1,
+            vec![
+                TextElement::Text("<a "),
+                TextElement::Text("fill=\""),
+                TextElement::Color(
+                    Color::RGBA(255, 0, 0, 128),
+                    "Red;fill-opacity:0.5",
+                    ColorContext {
+                        property: Some(ColorProperty::Fill),
+                        form: PropertyForm::Attribute {
+                            quote: '"',
+                            add_opacity: false
+                        },
+                        opacity: None,
+                    }
+                ),
+                TextElement::Text("\">")
+            ]
+        );
+
+        // each property uses its own opacity property
+        let colors = |input| -> Vec<_> {
+            text_with_colors(input, true)
+                .unwrap()
+                .1
+                .into_iter()
+                .filter_map(|element| match element {
+                    TextElement::Color(color, text, _) => Some((color, text)),
+                    TextElement::Text(_) => None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            colors("stroke:#f00; stroke-opacity: 50%;fill:#00f;fill-opacity:0.5"),
+            vec![
+                (Color::RGBA(255, 0, 0, 128), "#f00; stroke-opacity: 50%"),
+                (Color::RGBA(0, 0, 255, 128), "#00f;fill-opacity:0.5"),
+            ]
+        );
+        assert_eq!(
+            colors("stop-color:rgba(0,0,0,0.5);fill-opacity:0.5"),
+            vec![(Color::RGBA(0, 0, 0, 128), "rgba(0,0,0,0.5)")]
+        );
+        assert_eq!(
+            colors("#000;stop-opacity:0.5"),
+            vec![(Color::RGB(0, 0, 0), "#000")]
+        );
     }
 }
@@ -1,8 +1,9 @@
 use resvg::tiny_skia::{self, Pixmap};
 use thiserror::Error;
 
-use crate::bounds::{self, Bounds};
+use crate::bounds::{self, ReaperBounds, Rounding};
 
+#[derive(Hash)]
 pub enum UpscaleMode {
     /// No special assurance. Just upscale the entire contents
     Normal,
@@ -17,6 +18,16 @@ pub enum UpscaleMode {
 impl UpscaleMode {
     pub const VERTICAL_BUTTON: Self = Self::VerticalTiles(3);
     pub const HORIZONTAL_BUTTON: Self = Self::HorizontalTiles(3);
+
+    /// Number of tiles horizontally and vertically
+    pub fn tile_counts(&self) -> (u32, u32) {
+        match self {
+            UpscaleMode::Normal => (1, 1),
+            UpscaleMode::VerticalTiles(y) => (1, *y),
+            UpscaleMode::HorizontalTiles(x) => (*x, 1),
+            UpscaleMode::Grid { x, y } => (*x, *y),
+        }
+    }
 }
 
 /// Divide 2 integers. Only return the result if it has no remainder.
@@ -39,31 +50,52 @@ pub enum UpscaleError {
     NotDivisibleIntoTiles { w: u32, h: u32, tx: u32, ty: u32 },
     #[error("output image of size {0}x{1} cannot be created")]
     InvalidOutputResolution(u32, u32),
+    #[error("scaled {0} bounds overlap or don't fit inside the output image of size {1}x{2}")]
+    BoundsOutOfRange(&'static str, u32, u32),
+}
+
+/// Size and position of the SVG's view box. The size must be a whole number of pixels.
+pub fn view_box_size(tree: &resvg::usvg::Tree) -> Result<(u32, u32, f32, f32), UpscaleError> {
+    let viewbox = tree.view_box();
+    let x = viewbox.rect.x();
+    let y = viewbox.rect.y();
+    let width = viewbox.rect.width();
+    let height = viewbox.rect.height();
+    if width.trunc() != width || height.trunc() != height {
+        return Err(UpscaleError::FractionalInputResolution(width, height));
+    }
+    Ok((width as u32, height as u32, x, y))
+}
+
+/// Size of each tile of an image, excluding REAPER's borders. The image must divide evenly
+/// into the tiles.
+pub fn tile_size(width: u32, height: u32, mode: &UpscaleMode) -> Result<(u32, u32), UpscaleError> {
+    let (tiles_x, tiles_y) = mode.tile_counts();
+    let error = UpscaleError::NotDivisibleIntoTiles {
+        w: width,
+        h: height,
+        tx: tiles_x,
+        ty: tiles_y,
+    };
+    match (
+        divide_no_remainder(width, tiles_x),
+        divide_no_remainder(height, tiles_y),
+    ) {
+        (Some(tile_width), Some(tile_height)) => Ok((tile_width, tile_height)),
+        _ => Err(error),
+    }
 }
 
 /// Render a Tree normally at its normal scale
 pub fn render(tree: &resvg::usvg::Tree) -> Result<Pixmap, UpscaleError> {
-    let (outer_width, outer_height, x, y) = {
-        let viewbox = tree.view_box();
-        let x = viewbox.rect.x();
-        let y = viewbox.rect.y();
-        let width = viewbox.rect.width();
-        let height = viewbox.rect.height();
-        if width.trunc() != width || height.trunc() != height {
-            panic!(
-                "svg dimensions is fractional ({} x {}) {:?}",
-                width, height, viewbox
-            );
-        }
-        (width as u32, height as u32, x, y)
-    };
+    let (outer_width, outer_height, x, y) = view_box_size(tree)?;
 
     let mut pixmap = Pixmap::new(outer_width, outer_height).ok_or(
         UpscaleError::InvalidOutputResolution(outer_width, outer_height),
     )?;
 
     resvg::render(
-        &tree,
+        tree,
         tiny_skia::Transform::from_translate(-x, -y),
         &mut pixmap.as_mut(),
     );
@@ -71,59 +103,44 @@ pub fn render(tree: &resvg::usvg::Tree) -> Result<Pixmap, UpscaleError> {
     Ok(pixmap)
 }
 
-/// Render a Tree, upscaling it. This allows specifying 'tile size' to ensure that
-/// each inner tile is upscaled to an integer resolution, not a fractional resolution.
+/// The REAPER borders of a render
+#[derive(Clone, Copy)]
+pub enum Borders<'a> {
+    None,
+    /// The SVG has a 1px border with the bounds drawn in it
+    Drawn(&'a ReaperBounds),
+    /// The SVG has no border, a 1px border is added around it
+    Inserted(&'a ReaperBounds),
+}
+
+/// Render a Tree, upscaling or downscaling it. This allows specifying 'tile size' to ensure that
+/// each inner tile is scaled to an integer resolution, not a fractional resolution.
 pub fn render_upscaled(
     tree: &resvg::usvg::Tree,
     scale: f32,
     mode: &UpscaleMode,
-    pink_bounds: Option<&Bounds>,
-    yellow_bounds: Option<&Bounds>,
+    borders: Borders,
+    rounding: Rounding,
 ) -> Result<Pixmap, UpscaleError> {
     if scale <= 0.0 {
         return Err(UpscaleError::InvalidScale(scale));
     }
 
-    let has_bounds = pink_bounds.is_some() || yellow_bounds.is_some();
+    let bounds = match borders {
+        Borders::None => None,
+        Borders::Drawn(bounds) | Borders::Inserted(bounds) => Some(bounds),
+    };
+    let has_bounds = bounds.is_some();
 
     // calculate the target output size, given the upscale mode
-    let (outer_width, outer_height, x, y) = {
-        let viewbox = tree.view_box();
-        let x = viewbox.rect.x();
-        let y = viewbox.rect.y();
-        let width = viewbox.rect.width();
-        let height = viewbox.rect.height();
-        if width.trunc() != width || height.trunc() != height {
-            return Err(UpscaleError::FractionalInputResolution(width, height));
-        }
-        (width as u32, height as u32, x, y)
-    };
-    let (inner_width, inner_height) = if has_bounds {
-        (outer_width - 2, outer_height - 2)
-    } else {
-        (outer_width, outer_height)
+    let (outer_width, outer_height, x, y) = view_box_size(tree)?;
+    let (inner_width, inner_height) = match borders {
+        Borders::Drawn(_) => (outer_width - 2, outer_height - 2),
+        _ => (outer_width, outer_height),
     };
 
-    let (tiles_x, tiles_y) = match mode {
-        UpscaleMode::Normal => (1, 1),
-        UpscaleMode::VerticalTiles(y) => (1, *y),
-        UpscaleMode::HorizontalTiles(x) => (*x, 1),
-        UpscaleMode::Grid { x, y } => (*x, *y),
-    };
-    let tile_width =
-        divide_no_remainder(inner_width, tiles_x).ok_or(UpscaleError::NotDivisibleIntoTiles {
-            w: inner_width,
-            h: inner_height,
-            tx: tiles_x,
-            ty: tiles_y,
-        })?;
-    let tile_height =
-        divide_no_remainder(inner_height, tiles_y).ok_or(UpscaleError::NotDivisibleIntoTiles {
-            w: inner_width,
-            h: inner_height,
-            tx: tiles_x,
-            ty: tiles_y,
-        })?;
+    let (tiles_x, tiles_y) = mode.tile_counts();
+    let (tile_width, tile_height) = tile_size(inner_width, inner_height, mode)?;
 
     let final_tile_width = ((tile_width as f32) * scale).ceil() as u32;
     let final_tile_height = ((tile_height as f32) * scale).ceil() as u32;
@@ -139,44 +156,63 @@ pub fn render_upscaled(
     let mut pixmap = Pixmap::new(final_outer_width, final_outer_height).ok_or(
         UpscaleError::InvalidOutputResolution(final_outer_width, final_outer_height),
     )?;
-    let transform = if has_bounds {
-        tiny_skia::Transform::from_translate(-x, -y)
+    let transform = match borders {
+        Borders::Drawn(_) => tiny_skia::Transform::from_translate(-x, -y)
             .post_scale(
                 final_inner_width as f32 / inner_width as f32,
                 final_inner_height as f32 / inner_height as f32,
             )
             .pre_translate(-1.0, -1.0)
-            .post_translate(1.0, 1.0)
-    } else {
-        tiny_skia::Transform::from_translate(-x, -y).post_scale(
+            .post_translate(1.0, 1.0),
+        // leave room for the border
+        Borders::Inserted(_) => tiny_skia::Transform::from_translate(-x, -y)
+            .post_scale(
+                final_inner_width as f32 / inner_width as f32,
+                final_inner_height as f32 / inner_height as f32,
+            )
+            .post_translate(1.0, 1.0),
+        Borders::None => tiny_skia::Transform::from_translate(-x, -y).post_scale(
             final_outer_width as f32 / outer_width as f32,
             final_outer_height as f32 / outer_height as f32,
-        )
+        ),
     };
 
-    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    resvg::render(tree, transform, &mut pixmap.as_mut());
 
     // clear existing bounds and redraw them
-    if has_bounds {
-        // upscale the bounds
-        let actual_scale = (final_inner_width as f32 / inner_width as f32)
-            .max(final_inner_height as f32 / inner_height as f32);
+    if let Some(bounds) = bounds {
+        // upscale the bounds, tile snapping can make the scale differ on each axis
+        let scale_x = final_inner_width as f32 / inner_width as f32;
+        let scale_y = final_inner_height as f32 / inner_height as f32;
 
-        let pink_bounds = pink_bounds.unwrap().scale(actual_scale);
-        let yellow_bounds = yellow_bounds.unwrap().scale(actual_scale);
+        let pink_bounds = bounds.pink.scale(scale_x, scale_y, rounding);
+        let yellow_bounds = bounds.yellow.scale(scale_x, scale_y, rounding);
+        for (name, scaled) in [("pink", &pink_bounds), ("yellow", &yellow_bounds)] {
+            if !scaled.fits(final_inner_width, final_inner_height) {
+                return Err(UpscaleError::BoundsOutOfRange(
+                    name,
+                    final_outer_width,
+                    final_outer_height,
+                ));
+            }
+        }
 
         // redraw the bounds
         let pink_paint = {
-            let mut paint = tiny_skia::Paint::default();
-            paint.anti_alias = false;
-            paint.blend_mode = tiny_skia::BlendMode::Source;
+            let mut paint = tiny_skia::Paint {
+                anti_alias: false,
+                blend_mode: tiny_skia::BlendMode::Source,
+                ..Default::default()
+            };
             paint.set_color(tiny_skia::Color::from_rgba8(255, 0, 255, 255));
             paint
         };
         let yellow_paint = {
-            let mut paint = tiny_skia::Paint::default();
-            paint.anti_alias = false;
-            paint.blend_mode = tiny_skia::BlendMode::Source;
+            let mut paint = tiny_skia::Paint {
+                anti_alias: false,
+                blend_mode: tiny_skia::BlendMode::Source,
+                ..Default::default()
+            };
             paint.set_color(tiny_skia::Color::from_rgba8(255, 255, 0, 255));
             paint
         };
@@ -184,10 +220,254 @@ pub fn render_upscaled(
         {
             let mut pixmap_mut = pixmap.as_mut();
             bounds::erase_bounds(&mut pixmap_mut);
-            pink_bounds.paint(&mut pixmap_mut, &pink_paint);
-            yellow_bounds.paint(&mut pixmap_mut, &yellow_paint);
+            // edges without markers stay transparent, marked edges keep at least a pink
+            // corner, which yellow bounds paint over
+            pink_bounds.paint(&mut pixmap_mut, &pink_paint, &bounds.sides);
+            if !yellow_bounds.is_empty() {
+                yellow_bounds.paint(&mut pixmap_mut, &yellow_paint, &bounds.sides);
+            }
         }
     }
 
     Ok(pixmap)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bounds::{Bounds, Side};
+
+    use super::*;
+
+    fn parse_svg(text: &str) -> resvg::usvg::Tree {
+        let fontdb = resvg::usvg::fontdb::Database::new();
+        resvg::usvg::Tree::from_str(text, &resvg::usvg::Options::default(), &fontdb).unwrap()
+    }
+
+    #[test]
+    fn test_downscale_tiles() {
+        // 3 tiles of 14x11, each should be downscaled to 7x6
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="42" height="11" viewBox="0 0 42 11">
+                <rect width="42" height="11" fill="#336699"/>
+            </svg>"##,
+        );
+        let pixmap = render_upscaled(
+            &tree,
+            0.5,
+            &UpscaleMode::HORIZONTAL_BUTTON,
+            Borders::None,
+            Rounding::Ceil,
+        )
+        .unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (21, 6));
+    }
+
+    #[test]
+    fn test_downscale_bounds() {
+        // 20x10 image with a 1px border, pink bounds are 4px on the left and top
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="22" height="12" viewBox="0 0 22 12">
+                <rect x="1" y="1" width="20" height="10" fill="#336699"/>
+                <rect x="0" y="0" width="5" height="1" fill="#ff00ff"/>
+                <rect x="0" y="0" width="1" height="5" fill="#ff00ff"/>
+                <rect x="21" y="11" width="1" height="1" fill="#ff00ff"/>
+            </svg>"##,
+        );
+        let scale_1_pixmap = render(&tree).unwrap();
+        let bounds = bounds::parse_reaper_bounds(&scale_1_pixmap).unwrap();
+        let pink = &bounds.pink;
+        assert_eq!((pink.l, pink.t, pink.r, pink.b), (4, 4, 0, 0));
+
+        let pixmap = render_upscaled(
+            &tree,
+            0.5,
+            &UpscaleMode::Normal,
+            Borders::Drawn(&bounds),
+            Rounding::Ceil,
+        )
+        .unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (12, 7));
+
+        let pink = bounds::parse_reaper_bounds(&pixmap).unwrap().pink;
+        assert_eq!((pink.l, pink.t, pink.r, pink.b), (2, 2, 0, 0));
+    }
+
+    #[test]
+    fn test_upscale_partial_bounds() {
+        // 20x10 image with markers on the top and left edges only
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="22" height="12" viewBox="0 0 22 12">
+                <rect x="1" y="1" width="20" height="10" fill="#336699"/>
+                <rect x="0" y="0" width="5" height="1" fill="#ff00ff"/>
+                <rect x="0" y="0" width="1" height="1" fill="#ff00ff"/>
+            </svg>"##,
+        );
+        let bounds = bounds::parse_reaper_bounds(&render(&tree).unwrap()).unwrap();
+        assert_eq!(bounds.sides, vec![Side::Top, Side::Left]);
+
+        let pixmap = render_upscaled(
+            &tree,
+            2.0,
+            &UpscaleMode::Normal,
+            Borders::Drawn(&bounds),
+            Rounding::Ceil,
+        )
+        .unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (42, 22));
+
+        // the bottom and right edges are still missing
+        let upscaled = bounds::parse_reaper_bounds(&pixmap).unwrap();
+        assert_eq!(upscaled.sides, vec![Side::Top, Side::Left]);
+        assert_eq!((upscaled.pink.l, upscaled.pink.t), (8, 0));
+    }
+
+    #[test]
+    fn test_upscale_corner_markers() {
+        // every edge is marked only by its corner, so all bounds are empty
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="22" height="12" viewBox="0 0 22 12">
+                <rect x="1" y="1" width="20" height="10" fill="#336699"/>
+                <rect x="0" y="0" width="1" height="1" fill="#ff00ff"/>
+                <rect x="21" y="11" width="1" height="1" fill="#ff00ff"/>
+            </svg>"##,
+        );
+        let bounds = bounds::parse_reaper_bounds(&render(&tree).unwrap()).unwrap();
+        assert!(bounds.pink.is_empty() && bounds.yellow.is_empty());
+
+        let pixmap = render_upscaled(
+            &tree,
+            2.0,
+            &UpscaleMode::Normal,
+            Borders::Drawn(&bounds),
+            Rounding::Ceil,
+        )
+        .unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (42, 22));
+        let upscaled = bounds::parse_reaper_bounds(&pixmap).unwrap();
+        assert_eq!(upscaled.sides.len(), 4);
+    }
+
+    #[test]
+    fn test_corner_pixel_artwork() {
+        // a pink pixel in the corner of the artwork doesn't make a border
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" viewBox="0 0 20 10">
+                <rect x="1" y="1" width="18" height="8" fill="#336699"/>
+                <rect x="0" y="0" width="1" height="1" fill="#ff00ff"/>
+            </svg>"##,
+        );
+        let pixmap = render(&tree).unwrap();
+        assert!(bounds::parse_reaper_bounds(&pixmap).is_err());
+
+        // so the image is scaled as a whole, keeping the corner pixel
+        let pixmap = render_upscaled(
+            &tree,
+            2.0,
+            &UpscaleMode::Normal,
+            Borders::None,
+            Rounding::Ceil,
+        )
+        .unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (40, 20));
+        let corner = pixmap.pixel(1, 1).unwrap();
+        assert_eq!((corner.red(), corner.green(), corner.blue()), (255, 0, 255));
+    }
+
+    #[test]
+    fn test_upscale_bounds_per_axis() {
+        // 3x20 image of 3 tiles, the tiles snap to a width of 2, so the X scale is 2 while
+        // the Y scale is 1.5
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="5" height="22" viewBox="0 0 5 22">
+                <rect x="1" y="1" width="3" height="20" fill="#336699"/>
+                <rect x="0" y="0" width="1" height="11" fill="#ff00ff"/>
+                <rect x="4" y="21" width="1" height="1" fill="#ff00ff"/>
+            </svg>"##,
+        );
+        let bounds = bounds::parse_reaper_bounds(&render(&tree).unwrap()).unwrap();
+        assert_eq!(bounds.pink.t, 10);
+
+        let pixmap = render_upscaled(
+            &tree,
+            1.5,
+            &UpscaleMode::HORIZONTAL_BUTTON,
+            Borders::Drawn(&bounds),
+            Rounding::Ceil,
+        )
+        .unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (8, 32));
+
+        let pink = bounds::parse_reaper_bounds(&pixmap).unwrap().pink;
+        assert_eq!((pink.l, pink.t), (0, 15));
+    }
+
+    #[test]
+    fn test_insert_bounds() {
+        // 20x10 image without a border, the pink bounds are added around it
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" viewBox="0 0 20 10">
+                <rect width="20" height="10" fill="#336699"/>
+            </svg>"##,
+        );
+        let bounds = ReaperBounds::new(
+            Default::default(),
+            Bounds {
+                l: 4,
+                t: 0,
+                r: 4,
+                b: 0,
+            },
+        );
+
+        for (scale, size, l) in [(1.0, (22, 12), 4), (1.5, (32, 17), 6)] {
+            let pixmap = render_upscaled(
+                &tree,
+                scale,
+                &UpscaleMode::Normal,
+                Borders::Inserted(&bounds),
+                Rounding::Ceil,
+            )
+            .unwrap();
+            assert_eq!((pixmap.width(), pixmap.height()), size);
+
+            let detected = bounds::parse_reaper_bounds(&pixmap).unwrap();
+            assert_eq!((detected.pink.l, detected.pink.r), (l, l));
+            // the artwork starts inside the border
+            let pixel = pixmap.pixel(1, 1).unwrap();
+            assert_eq!((pixel.red(), pixel.alpha()), (0x33, 255));
+        }
+    }
+
+    #[test]
+    fn test_overlapping_bounds() {
+        // pink bounds splitting the image in halves, which overlap once 1.5 rounds them up
+        let tree = parse_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10" viewBox="0 0 10 10">
+                <rect width="10" height="10" fill="#336699"/>
+            </svg>"##,
+        );
+        let bounds = ReaperBounds::new(
+            Default::default(),
+            Bounds {
+                l: 5,
+                t: 0,
+                r: 5,
+                b: 0,
+            },
+        );
+        let upscale = |rounding| {
+            render_upscaled(
+                &tree,
+                1.5,
+                &UpscaleMode::Normal,
+                Borders::Inserted(&bounds),
+                rounding,
+            )
+        };
+        assert!(matches!(
+            upscale(Rounding::Ceil),
+            Err(UpscaleError::BoundsOutOfRange("pink", 17, 17))
+        ));
+        assert!(upscale(Rounding::Floor).is_ok());
+    }
+}
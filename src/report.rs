@@ -0,0 +1,518 @@
+//! Output formats of the `colors` command
+
+use std::{cmp::Reverse, fmt::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    cli::ColorFormat, delta_e::ciede2000, map_colors::ColorLocation, notation::to_hex,
+    parser::Color,
+};
+
+/// A color, and the files that use it
+pub struct ColorReport<'a> {
+    pub color: Color,
+    pub files: Vec<(&'a Path, Vec<ColorLocation>)>,
+    /// Colors that are perceptually close to this one, with their CIEDE2000 difference
+    pub similar: Vec<(ColorReport<'a>, f32)>,
+}
+
+impl ColorReport<'_> {
+    /// How often the color occurs in all files
+    pub fn uses(&self) -> usize {
+        self.files
+            .iter()
+            .map(|(_, locations)| locations.len())
+            .sum()
+    }
+
+    /// How often the color occurs, together with the similar colors it represents
+    pub fn total_uses(&self) -> usize {
+        self.uses()
+            + self
+                .similar
+                .iter()
+                .map(|(similar, _)| similar.total_uses())
+                .sum::<usize>()
+    }
+
+    /// The count to print: the number of files using the color, or with clustering, how
+    /// often the color and the colors it represents occur
+    fn count(&self, opt: &ReportOptions) -> usize {
+        match opt.cluster {
+            true => self.total_uses(),
+            false => self.files.len(),
+        }
+    }
+}
+
+/// Group colors that are within `max_delta_e` of each other. The most used color of each
+/// group represents it, and groups are sorted by their total uses. Colors are added to the
+/// closest group, and only grouped with colors of the same alpha. REAPER's reserved colors
+/// are never grouped.
+pub fn cluster_reports(mut reports: Vec<ColorReport>, max_delta_e: f32) -> Vec<ColorReport> {
+    reports.sort_by_key(|report| (Reverse(report.uses()), to_hex(&report.color)));
+    let mut clusters: Vec<ColorReport> = Vec::new();
+    for report in reports {
+        let reserved = report.color.is_reaper_reserved();
+        let closest = clusters
+            .iter_mut()
+            .filter(|cluster| !reserved && !cluster.color.is_reaper_reserved())
+            .filter(|cluster| cluster.color.a() == report.color.a())
+            .map(|cluster| (ciede2000(&cluster.color, &report.color), cluster))
+            .filter(|(delta_e, _)| *delta_e <= max_delta_e)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b));
+        match closest {
+            Some((delta_e, cluster)) => cluster.similar.push((report, delta_e)),
+            None => clusters.push(report),
+        }
+    }
+    clusters.sort_by_key(|cluster| (Reverse(cluster.total_uses()), to_hex(&cluster.color)));
+    clusters
+}
+
+pub struct ReportOptions {
+    /// Print the number of files using each color. When clustering, this is instead how
+    /// often each color occurs, summed over the colors a group's color represents, since
+    /// occurrences pick and rank the groups. JSON and CSV always include it.
+    pub count: bool,
+    /// Print where each color is used. Only the text, JSON and CSV formats list locations.
+    pub locations: bool,
+    /// Print the alpha of colors
+    pub alpha: bool,
+    /// Colors were grouped with `cluster_reports`
+    pub cluster: bool,
+}
+
+#[derive(Serialize)]
+struct JsonLocation<'a> {
+    file: &'a Path,
+    #[serde(flatten)]
+    location: &'a ColorLocation,
+}
+
+#[derive(Serialize)]
+struct JsonColor<'a> {
+    hex: String,
+    r: u8,
+    g: u8,
+    b: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    a: Option<f32>,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locations: Option<Vec<JsonLocation<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_e: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    similar: Vec<JsonColor<'a>>,
+}
+
+/// The alpha of a color as an opacity, opaque colors have an opacity of 1
+fn opacity(color: &Color) -> f32 {
+    color.a().unwrap_or(255) as f32 / 255.0
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+fn text_color(
+    result: &mut String,
+    report: &ColorReport,
+    opt: &ReportOptions,
+    hex: bool,
+    delta_e: Option<f32>,
+) {
+    let color = match hex {
+        true => to_hex(&report.color),
+        false => report.color.to_string(),
+    };
+    let indent = match delta_e {
+        Some(_) => "    ",
+        None => "",
+    };
+    write!(result, "{}", indent).unwrap();
+    if opt.count {
+        write!(result, "{} ", report.count(opt)).unwrap();
+    }
+    write!(result, "{}", color).unwrap();
+    if let Some(delta_e) = delta_e {
+        write!(result, " (ΔE {:.2})", delta_e).unwrap();
+    }
+    result.push('\n');
+
+    if opt.locations && !hex {
+        for (path, locations) in &report.files {
+            for location in locations {
+                writeln!(result, "{}    {}:{}", indent, path.display(), location).unwrap();
+            }
+        }
+    }
+}
+
+/// One line per color. Similar colors are indented under the color representing them.
+fn text(reports: &[ColorReport], opt: &ReportOptions, hex: bool) -> String {
+    let mut result = String::new();
+    for report in reports {
+        text_color(&mut result, report, opt, hex, None);
+        for (similar, delta_e) in &report.similar {
+            text_color(&mut result, similar, opt, hex, Some(*delta_e));
+        }
+    }
+    result
+}
+
+fn json_color<'a>(
+    report: &'a ColorReport,
+    opt: &ReportOptions,
+    delta_e: Option<f32>,
+) -> JsonColor<'a> {
+    JsonColor {
+        hex: to_hex(&report.color),
+        r: report.color.r(),
+        g: report.color.g(),
+        b: report.color.b(),
+        a: opt.alpha.then(|| opacity(&report.color)),
+        count: report.count(opt),
+        locations: opt.locations.then(|| {
+            report
+                .files
+                .iter()
+                .flat_map(|(file, locations)| {
+                    locations
+                        .iter()
+                        .map(|location| JsonLocation { file, location })
+                })
+                .collect()
+        }),
+        delta_e,
+        similar: report
+            .similar
+            .iter()
+            .map(|(similar, delta_e)| json_color(similar, opt, Some(*delta_e)))
+            .collect(),
+    }
+}
+
+fn json(reports: &[ColorReport], opt: &ReportOptions) -> String {
+    let colors: Vec<_> = reports
+        .iter()
+        .map(|report| json_color(report, opt, None))
+        .collect();
+    let mut result = serde_json::to_string_pretty(&colors).unwrap();
+    result.push('\n');
+    result
+}
+
+fn csv_color(result: &mut String, report: &ColorReport, opt: &ReportOptions, group: &Color) {
+    let color = &report.color;
+    let mut fields = vec![
+        to_hex(color),
+        color.r().to_string(),
+        color.g().to_string(),
+        color.b().to_string(),
+    ];
+    if opt.alpha {
+        fields.push(opacity(color).to_string());
+    }
+    let count = match opt.cluster {
+        true => report.uses(),
+        false => report.files.len(),
+    };
+    fields.push(count.to_string());
+    if opt.cluster {
+        fields.push(to_hex(group));
+    }
+
+    if !opt.locations {
+        writeln!(result, "{}", fields.join(",")).unwrap();
+        return;
+    }
+    for (path, locations) in &report.files {
+        for location in locations {
+            let id = location.id.as_deref().unwrap_or_default();
+            writeln!(
+                result,
+                "{},{},{},{},{}",
+                fields.join(","),
+                csv_field(&path.to_string_lossy()),
+                location.line,
+                location.column,
+                csv_field(id)
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// One row per color, or one row per location with `--locations`. When clustering, the
+/// `cluster` column has the color representing the group of each color, and the counts of
+/// a group's rows add up to its total.
+fn csv(reports: &[ColorReport], opt: &ReportOptions) -> String {
+    let mut header = vec!["hex", "r", "g", "b"];
+    if opt.alpha {
+        header.push("a");
+    }
+    header.push("count");
+    if opt.cluster {
+        header.push("cluster");
+    }
+    if opt.locations {
+        header.extend(["file", "line", "column", "id"]);
+    }
+
+    let mut result = header.join(",") + "\n";
+    for report in reports {
+        csv_color(&mut result, report, opt, &report.color);
+        for (similar, _) in &report.similar {
+            csv_color(&mut result, similar, opt, &report.color);
+        }
+    }
+    result
+}
+
+/// A GIMP palette, which Affinity, Inkscape and Krita can import as well. Palettes don't
+/// have an alpha channel, so it is only kept in the color names. Similar colors are left
+/// out, so clustering gives a deduplicated palette.
+fn gpl(reports: &[ColorReport], opt: &ReportOptions) -> String {
+    let mut result = String::from("GIMP Palette\nName: svgthing\n#\n");
+    for report in reports {
+        let color = &report.color;
+        let mut name = to_hex(color);
+        if opt.count {
+            write!(name, " ({})", report.count(opt)).unwrap();
+        }
+        writeln!(
+            result,
+            "{:3} {:3} {:3}\t{}",
+            color.r(),
+            color.g(),
+            color.b(),
+            name
+        )
+        .unwrap();
+    }
+    result
+}
+
+/// CSS custom properties, numbered in the order of the colors. Similar colors are left out.
+fn css(reports: &[ColorReport], opt: &ReportOptions) -> String {
+    let mut result = String::from(":root {\n");
+    for (i, report) in reports.iter().enumerate() {
+        write!(result, "  --color-{}: {};", i + 1, to_hex(&report.color)).unwrap();
+        if opt.count {
+            write!(result, " /* {} */", report.count(opt)).unwrap();
+        }
+        result.push('\n');
+    }
+    result.push_str("}\n");
+    result
+}
+
+/// A palette file that `--palette` can load, with the colors named in their order like the
+/// CSS custom properties. Similar colors are left out.
+fn toml(reports: &[ColorReport], opt: &ReportOptions) -> String {
+    let mut result = String::new();
+    for (i, report) in reports.iter().enumerate() {
+        write!(result, "color-{} = \"{}\"", i + 1, to_hex(&report.color)).unwrap();
+        if opt.count {
+            write!(result, " # {}", report.count(opt)).unwrap();
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// Format the colors found by the `colors` command
+pub fn format_report(reports: &[ColorReport], format: ColorFormat, opt: &ReportOptions) -> String {
+    match format {
+        ColorFormat::Text => text(reports, opt, false),
+        ColorFormat::Hex => text(reports, opt, true),
+        ColorFormat::Json => json(reports, opt),
+        ColorFormat::Csv => csv(reports, opt),
+        ColorFormat::Gpl => gpl(reports, opt),
+        ColorFormat::Css => css(reports, opt),
+        ColorFormat::Toml => toml(reports, opt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Palette;
+
+    fn reports() -> Vec<ColorReport<'static>> {
+        let location = ColorLocation {
+            line: 2,
+            column: 5,
+            id: Some("a,b".into()),
+        };
+        vec![
+            ColorReport {
+                color: Color::RGBA(255, 0, 0, 51),
+                files: vec![(Path::new("a.svg"), vec![location])],
+                similar: vec![],
+            },
+            ColorReport {
+                color: Color::RGB(0x66, 0x99, 0xcc),
+                files: vec![],
+                similar: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_formats() {
+        let opt = ReportOptions {
+            count: true,
+            locations: false,
+            alpha: true,
+            cluster: false,
+        };
+        let format = |format| format_report(&reports(), format, &opt);
+        assert_eq!(format(ColorFormat::Hex), "1 #ff000033\n0 #6699cc\n");
+        assert_eq!(
+            format(ColorFormat::Csv),
+            "hex,r,g,b,a,count\n#ff000033,255,0,0,0.2,1\n#6699cc,102,153,204,1,0\n"
+        );
+        assert_eq!(
+            format(ColorFormat::Gpl),
+            "GIMP Palette\nName: svgthing\n#\n255   0   0\t#ff000033 (1)\n102 153 204\t#6699cc (0)\n"
+        );
+        assert_eq!(
+            format(ColorFormat::Css),
+            ":root {\n  --color-1: #ff000033; /* 1 */\n  --color-2: #6699cc; /* 0 */\n}\n"
+        );
+        assert_eq!(
+            format(ColorFormat::Toml),
+            "color-1 = \"#ff000033\" # 1\ncolor-2 = \"#6699cc\" # 0\n"
+        );
+    }
+
+    #[test]
+    fn test_palette_roundtrip() {
+        let opt = ReportOptions {
+            count: true,
+            locations: false,
+            alpha: true,
+            cluster: false,
+        };
+        let text = format_report(&reports(), ColorFormat::Toml, &opt);
+        let palette = Palette::parse(&text, Path::new("palette.toml")).unwrap();
+        assert_eq!(palette.resolve("color-1"), Some(Color::RGBA(255, 0, 0, 51)));
+        assert_eq!(
+            palette.resolve("color-2"),
+            Some(Color::RGB(0x66, 0x99, 0xcc))
+        );
+    }
+
+    #[test]
+    fn test_locations() {
+        let opt = ReportOptions {
+            count: false,
+            locations: true,
+            alpha: false,
+            cluster: false,
+        };
+        assert_eq!(
+            format_report(&reports(), ColorFormat::Csv, &opt),
+            "hex,r,g,b,count,file,line,column,id\n#ff000033,255,0,0,1,a.svg,2,5,\"a,b\"\n"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_report(&reports(), ColorFormat::Json, &opt)).unwrap();
+        assert_eq!(
+            json[0],
+            serde_json::json!({
+                "hex": "#ff000033",
+                "r": 255,
+                "g": 0,
+                "b": 0,
+                "count": 1,
+                "locations": [{"file": "a.svg", "line": 2, "column": 5, "id": "a,b"}],
+            })
+        );
+    }
+
+    #[test]
+    fn test_cluster() {
+        let location = |line| ColorLocation {
+            line,
+            column: 1,
+            id: None,
+        };
+        let report = |color, files: &[usize]| ColorReport {
+            color,
+            files: files
+                .iter()
+                .map(|uses| (Path::new("a.svg"), (1..=*uses).map(location).collect()))
+                .collect(),
+            similar: vec![],
+        };
+        // sorted by files, but #333333 occurs more often than #323232
+        let reports = vec![
+            report(Color::RGB(0x32, 0x32, 0x32), &[1, 1, 1]),
+            report(Color::RGB(0x66, 0x99, 0xcc), &[3, 3]),
+            report(Color::RGB(0x33, 0x33, 0x33), &[5]),
+            report(Color::RGB(0x34, 0x34, 0x34), &[1]),
+            report(Color::RGBA(0x32, 0x32, 0x32, 128), &[2]),
+        ];
+        // without clustering, the count is the number of files using a color
+        let opt = ReportOptions {
+            count: true,
+            locations: false,
+            alpha: false,
+            cluster: false,
+        };
+        assert_eq!(
+            format_report(&reports[..3], ColorFormat::Hex, &opt),
+            "3 #323232\n2 #6699cc\n1 #333333\n"
+        );
+
+        let clusters = cluster_reports(reports, 1.0);
+        let colors: Vec<_> = clusters
+            .iter()
+            .map(|cluster| {
+                let similar: Vec<_> = cluster
+                    .similar
+                    .iter()
+                    .map(|(r, _)| to_hex(&r.color))
+                    .collect();
+                (to_hex(&cluster.color), similar)
+            })
+            .collect();
+        assert_eq!(
+            colors,
+            vec![
+                ("#333333".into(), vec!["#323232".into(), "#343434".into()]),
+                ("#6699cc".into(), vec![]),
+                ("#32323280".into(), vec![]),
+            ]
+        );
+
+        let opt = ReportOptions {
+            count: true,
+            locations: false,
+            alpha: false,
+            cluster: true,
+        };
+        assert_eq!(
+            format_report(&clusters[..1], ColorFormat::Hex, &opt),
+            "9 #333333\n    3 #323232 (ΔE 0.32)\n    1 #343434 (ΔE 0.32)\n"
+        );
+        assert_eq!(
+            format_report(&clusters[..1], ColorFormat::Csv, &opt),
+            "hex,r,g,b,count,cluster\n#333333,51,51,51,5,#333333\n\
+             #323232,50,50,50,3,#333333\n#343434,52,52,52,1,#333333\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&format_report(&clusters[..1], ColorFormat::Json, &opt)).unwrap();
+        assert_eq!(json[0]["count"], 9);
+        assert_eq!(json[0]["similar"][0]["count"], 3);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    cli::{Output, OutputPattern, OutputTemplate, RenderTask},
+    inputs::InputFile,
+};
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("unknown placeholder {{{0}}} in output template {1:?}")]
+    UnknownPlaceholder(String, String),
+    #[error("unclosed placeholder in output template {0:?}")]
+    UnclosedPlaceholder(String),
+    #[error("output template {0:?} uses {{variant}} but no variant was given")]
+    MissingVariant(String),
+    #[error("multiple outputs are written to {0}")]
+    DuplicateOutput(PathBuf),
+}
+
+/// Values that placeholders in an output template are replaced with
+pub struct TemplateVars<'a> {
+    pub input: &'a Path,
+    pub rel_dir: &'a Path,
+    pub scale: f32,
+    pub variant: Option<&'a str>,
+}
+
+impl TemplateVars<'_> {
+    fn get(&self, name: &str, template: &str) -> Result<String, TemplateError> {
+        Ok(match name {
+            "dir" => match self.input.parent() {
+                Some(dir) if dir != Path::new("") => dir.to_string_lossy().into_owned(),
+                _ => ".".into(),
+            },
+            "rel_dir" => match self.rel_dir {
+                dir if dir != Path::new("") => dir.to_string_lossy().into_owned(),
+                _ => ".".into(),
+            },
+            "stem" => self
+                .input
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            "scale" => self.scale.to_string(),
+            "scale_pct" => ((self.scale * 100.0).round() as u32).to_string(),
+            "variant" => self
+                .variant
+                .ok_or_else(|| TemplateError::MissingVariant(template.into()))?
+                .into(),
+            _ => {
+                return Err(TemplateError::UnknownPlaceholder(
+                    name.into(),
+                    template.into(),
+                ))
+            }
+        })
+    }
+}
+
+/// Replace the placeholders in an output template:
+///
+/// - `{dir}`: directory of the input SVG
+/// - `{rel_dir}`: directory of the input SVG, relative to the input directory or glob pattern
+/// - `{stem}`: file name of the input SVG, without the extension
+/// - `{scale}`: scale of the output, e.g. `1.5`
+/// - `{scale_pct}`: scale of the output as a percentage, e.g. `150`
+/// - `{variant}`: name of the variant being rendered
+pub fn expand(template: &str, vars: &TemplateVars) -> Result<String, TemplateError> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| TemplateError::UnclosedPlaceholder(template.into()))?;
+        result.push_str(&vars.get(&rest[start + 1..start + end], template)?);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Expand an output template into one output per scale
+pub fn expand_outputs(
+    template: &OutputTemplate,
+    input: &InputFile,
+) -> Result<Vec<Output>, TemplateError> {
+    template
+        .scales
+        .iter()
+        .map(|&scale| {
+            let output = match &template.pattern {
+                OutputPattern::Template { template: pattern } => {
+                    let vars = TemplateVars {
+                        input: &input.path,
+                        rel_dir: &input.rel_dir,
+                        scale,
+                        variant: template.variant.as_deref(),
+                    };
+                    expand(pattern, &vars)?.into()
+                }
+                OutputPattern::Dir { dir } => mirrored_output(dir, input, scale),
+            };
+            Ok(Output { output, scale })
+        })
+        .collect()
+}
+
+/// Output path that mirrors the input tree into `dir`, e.g. `svg/tcp/knob.svg` at a scale
+/// of 1.5 becomes `dir/150/tcp/knob.png`
+fn mirrored_output(dir: &Path, input: &InputFile, scale: f32) -> PathBuf {
+    let mut output = dir.to_path_buf();
+    if scale != 1.0 {
+        output.push(((scale * 100.0).round() as u32).to_string());
+    }
+    output.push(&input.rel_dir);
+    output.push(input.path.file_stem().unwrap_or_default());
+    output.set_extension("png");
+    output
+}
+
+/// Ensure that no two outputs are written to the same path
+pub fn check_duplicate_outputs(tasks: &[RenderTask]) -> Result<(), TemplateError> {
+    let mut paths = HashSet::new();
+    for output in tasks.iter().flat_map(|task| &task.outputs) {
+        if !paths.insert(&output.output) {
+            return Err(TemplateError::DuplicateOutput(output.output.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(input: &str, scale: f32) -> TemplateVars<'_> {
+        TemplateVars {
+            input: Path::new(input),
+            rel_dir: Path::new("tcp"),
+            scale,
+            variant: Some("dark"),
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expand("{dir}/{scale_pct}/{stem}.png", &vars("svg/button.svg", 1.5)).unwrap(),
+            "svg/150/button.png"
+        );
+        assert_eq!(
+            expand("{dir}/{stem}@{scale}x.png", &vars("button.svg", 2.0)).unwrap(),
+            "./button@2x.png"
+        );
+        assert_eq!(
+            expand("out/{variant}/{stem}.png", &vars("svg/knob.svg", 1.0)).unwrap(),
+            "out/dark/knob.png"
+        );
+        assert_eq!(
+            expand("png/{rel_dir}/{stem}.png", &vars("svg/tcp/knob.svg", 1.0)).unwrap(),
+            "png/tcp/knob.png"
+        );
+    }
+
+    #[test]
+    fn test_mirrored_output() {
+        let input = InputFile {
+            path: PathBuf::from("svg/tcp/knob.svg"),
+            rel_dir: PathBuf::from("tcp"),
+        };
+        assert_eq!(
+            mirrored_output(Path::new("png"), &input, 1.0),
+            PathBuf::from("png/tcp/knob.png")
+        );
+        assert_eq!(
+            mirrored_output(Path::new("png"), &input, 1.5),
+            PathBuf::from("png/150/tcp/knob.png")
+        );
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        assert!(matches!(
+            expand("{name}.png", &vars("a.svg", 1.0)),
+            Err(TemplateError::UnknownPlaceholder(..))
+        ));
+        assert!(matches!(
+            expand("{stem.png", &vars("a.svg", 1.0)),
+            Err(TemplateError::UnclosedPlaceholder(..))
+        ));
+
+        let no_variant = TemplateVars {
+            input: Path::new("a.svg"),
+            rel_dir: Path::new(""),
+            scale: 1.0,
+            variant: None,
+        };
+        assert!(matches!(
+            expand("{variant}.png", &no_variant),
+            Err(TemplateError::MissingVariant(..))
+        ));
+    }
+}
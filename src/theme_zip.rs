@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+use resvg::tiny_skia::Pixmap;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    cli::{Output, RenderTask, ThemeZipOptions},
+    error::Error,
+};
+
+/// Collects rendered images in memory, then writes them into a `.ReaperThemeZip` archive.
+///
+/// The archive contains `<name>.ReaperTheme` at the root, and the images in a `<name>/`
+/// folder. Images rendered at other scales go into hi-DPI subfolders, e.g. `<name>/150/`.
+pub struct ThemeArchive {
+    options: ThemeZipOptions,
+    name: String,
+    images: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl ThemeArchive {
+    pub fn new(options: &ThemeZipOptions) -> Self {
+        let name = options.name.clone().unwrap_or_else(|| {
+            options
+                .path
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+
+        Self {
+            options: options.clone(),
+            name,
+            images: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Path of an output image inside the archive
+    fn entry_name(&self, output: &Output) -> String {
+        let file_name = output
+            .output
+            .file_name()
+            .map(|x| x.to_string_lossy())
+            .unwrap_or_default();
+
+        if output.scale == 1.0 {
+            format!("{}/{}", self.name, file_name)
+        } else {
+            let scale_pct = (output.scale * 100.0).round() as u32;
+            format!("{}/{}/{}", self.name, scale_pct, file_name)
+        }
+    }
+
+    /// Ensure that no two outputs end up at the same path in the archive
+    pub fn check_entries(&self, tasks: &[RenderTask]) -> Result<(), Error> {
+        let mut entries = HashSet::new();
+        for output in tasks.iter().flat_map(|task| &task.outputs) {
+            let entry = self.entry_name(output);
+            if !entries.insert(entry.clone()) {
+                return Err(Error::DuplicateArchiveEntry(entry));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_image(&self, pixmap: &Pixmap, output: &Output) -> Result<(), Error> {
+        let data = pixmap
+            .encode_png()
+            .map_err(|err| Error::Write(output.output.clone(), err.to_string()))?;
+
+        self.images
+            .lock()
+            .unwrap()
+            .insert(self.entry_name(output), data);
+        Ok(())
+    }
+
+    /// Write the archive with all images added so far
+    pub fn write(&self) -> Result<(), Error> {
+        let path = &self.options.path;
+        let file = File::create(path).map_err(|err| Error::Write(path.clone(), err.to_string()))?;
+
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let mut add_file = |name: String, source: &Path| -> Result<(), Error> {
+            let data = fs::read(source).map_err(|err| Error::Read(source.to_owned(), err))?;
+            zip.start_file(name, options)?;
+            zip.write_all(&data)
+                .map_err(|err| Error::Write(path.clone(), err.to_string()))
+        };
+
+        if let Some(theme_file) = &self.options.theme_file {
+            add_file(format!("{}.ReaperTheme", self.name), theme_file)?;
+        }
+        if let Some(rtconfig) = &self.options.rtconfig {
+            add_file(format!("{}/rtconfig.txt", self.name), rtconfig)?;
+        }
+
+        // PNGs are already compressed
+        let image_options = options.compression_method(CompressionMethod::Stored);
+        for (name, data) in self.images.lock().unwrap().iter() {
+            zip.start_file(name, image_options)?;
+            zip.write_all(data)
+                .map_err(|err| Error::Write(path.clone(), err.to_string()))?;
+        }
+
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn output(path: &str, scale: f32) -> Output {
+        Output {
+            output: PathBuf::from(path),
+            scale,
+        }
+    }
+
+    #[test]
+    fn test_entry_name() {
+        let archive = ThemeArchive::new(&ThemeZipOptions {
+            path: PathBuf::from("dist/Dark.ReaperThemeZip"),
+            name: None,
+            theme_file: None,
+            rtconfig: None,
+        });
+
+        assert_eq!(
+            archive.entry_name(&output("png/button.png", 1.0)),
+            "Dark/button.png"
+        );
+        assert_eq!(
+            archive.entry_name(&output("png/150/button.png", 1.5)),
+            "Dark/150/button.png"
+        );
+        assert_eq!(
+            archive.entry_name(&output("button@2x.png", 2.0)),
+            "Dark/200/button@2x.png"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// How often the watched paths are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Time to wait after a change is detected, so editors can finish writing the file
+const SETTLE_DELAY: Duration = Duration::from_millis(100);
+
+type Snapshot = HashMap<PathBuf, Option<SystemTime>>;
+
+/// Watches files and directories for changes by polling their modification times
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    snapshot: Snapshot,
+}
+
+fn add_to_snapshot(snapshot: &mut Snapshot, path: &Path) {
+    let Ok(metadata) = fs::metadata(path) else {
+        // record missing files, so they are reported when they are created
+        snapshot.insert(path.to_owned(), None);
+        return;
+    };
+
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            add_to_snapshot(snapshot, &entry.path());
+        }
+    } else {
+        snapshot.insert(path.to_owned(), metadata.modified().ok());
+    }
+}
+
+impl Watcher {
+    /// Start watching the given paths. Directories are watched recursively.
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let mut watcher = Self {
+            paths: paths.into_iter().collect(),
+            snapshot: HashMap::new(),
+        };
+        watcher.snapshot = watcher.take_snapshot();
+        watcher
+    }
+
+    fn take_snapshot(&self) -> Snapshot {
+        let mut snapshot = HashMap::new();
+        for path in &self.paths {
+            add_to_snapshot(&mut snapshot, path);
+        }
+        snapshot
+    }
+
+    /// The number of files being watched, counting the files in watched directories and
+    /// missing files that are waited for
+    pub fn file_count(&self) -> usize {
+        self.snapshot.len()
+    }
+
+    /// Block until any of the watched paths change, then return the changed paths.
+    /// Paths that were added or removed count as changed.
+    pub fn wait_for_changes(&mut self) -> HashSet<PathBuf> {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            if self.take_snapshot() == self.snapshot {
+                continue;
+            }
+
+            thread::sleep(SETTLE_DELAY);
+            let snapshot = self.take_snapshot();
+
+            let changed: HashSet<PathBuf> = snapshot
+                .iter()
+                .filter(|(path, mtime)| self.snapshot.get(*path) != Some(mtime))
+                .map(|(path, _)| path.clone())
+                .chain(
+                    self.snapshot
+                        .keys()
+                        .filter(|path| !snapshot.contains_key(*path))
+                        .cloned(),
+                )
+                .collect();
+
+            self.snapshot = snapshot;
+            if !changed.is_empty() {
+                return changed;
+            }
+        }
+    }
+}